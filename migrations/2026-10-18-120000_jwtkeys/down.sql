@@ -0,0 +1,14 @@
+DROP TABLE IF EXISTS jwt_secrets;
+
+CREATE TABLE IF NOT EXISTS users_migration (
+  id INTEGER NOT NULL PRIMARY KEY,
+  email TEXT NOT NULL UNIQUE,
+  password TEXT NOT NULL
+);
+INSERT INTO users_migration (
+  id,
+  email,
+  password
+) SELECT id, email, password FROM users;
+DROP TABLE users;
+ALTER TABLE users_migration RENAME TO users;
@@ -0,0 +1,7 @@
+ALTER TABLE users ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS jwt_secrets (
+  kid TEXT NOT NULL PRIMARY KEY,
+  secret TEXT NOT NULL,
+  created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS login_failures (
+  scope TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  failures INTEGER NOT NULL DEFAULT 0,
+  locked_until DATETIME,
+  modified DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (scope, subject)
+);
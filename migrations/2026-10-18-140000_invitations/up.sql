@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS invitations (
+  id INTEGER NOT NULL PRIMARY KEY,
+  code_hash TEXT NOT NULL UNIQUE,
+  max_uses INTEGER NOT NULL DEFAULT 1,
+  uses INTEGER NOT NULL DEFAULT 0,
+  expires DATETIME NOT NULL,
+  created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+  created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS users_migration (
+  id INTEGER NOT NULL PRIMARY KEY,
+  email TEXT NOT NULL UNIQUE,
+  password TEXT NOT NULL,
+  generation INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO users_migration (
+  id,
+  email,
+  password,
+  generation
+) SELECT id, email, password, generation FROM users;
+DROP TABLE users;
+ALTER TABLE users_migration RENAME TO users;
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT 0;
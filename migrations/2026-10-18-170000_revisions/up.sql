@@ -0,0 +1 @@
+ALTER TABLE passwords ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS passwords_site_canonical;
+ALTER TABLE passwords DROP COLUMN site_canonical;
@@ -0,0 +1,2 @@
+ALTER TABLE passwords ADD COLUMN site_canonical TEXT NOT NULL DEFAULT '';
+CREATE INDEX IF NOT EXISTS passwords_site_canonical ON passwords (user_id, site_canonical);
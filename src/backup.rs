@@ -0,0 +1,84 @@
+//
+// backup.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use diesel::{self, migration::MigrationSource, prelude::*, sql_types::Text, sqlite::Sqlite};
+use diesel_migrations::MigrationHarness;
+use uuid::Uuid;
+
+use crate::MIGRATIONS;
+
+pub fn backup(c: &mut SqliteConnection, backup_path: &str) -> Result<usize, String> {
+    // SQLite writes a consistent copy of the database even while other connections are using it.
+    // The copy has the signing keys and the secrets of the users, so the file is created first
+    // only readable by the owner, VACUUM INTO keeps the permissions of an empty file
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(backup_path).map_err(|e| e.to_string())?;
+    let result = diesel::sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(backup_path)
+        .execute(c)
+        .map_err(|e| e.to_string());
+    if result.is_err() {
+        let _ = fs::remove_file(backup_path);
+    }
+    result
+}
+
+pub fn private_directory() -> Result<PathBuf, String> {
+    // Directory in the temporary one that only the owner can enter, for the backups that are
+    // downloaded
+    let directory = std::env::temp_dir().join(format!("rockpass-backup-{}", Uuid::new_v4()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(&directory).map_err(|e| e.to_string())?;
+    Ok(directory)
+}
+
+pub fn restore(backup_path: &str, database_path: &str) -> Result<usize, String> {
+    // Prepare a copy next to the database so it is swapped in with a single rename
+    if ! Path::new(backup_path).is_file() {
+        return Err(format!("Backup {} not found", backup_path));
+    }
+    let restored_path = format!("{}.restore", database_path);
+    fs::copy(backup_path, &restored_path).map_err(|e| format!("Cannot copy the backup {}: {}", backup_path, e))?;
+    let result = prepare(&restored_path).and_then(|applied_migrations| {
+        fs::rename(&restored_path, database_path).map_err(|e| format!("Cannot replace the database {}: {}", database_path, e))?;
+        Ok(applied_migrations)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&restored_path);
+    }
+    result
+}
+
+fn prepare(restored_path: &str) -> Result<usize, String> {
+    // The backup must come from this or an older version of Rockpass, in the last case the
+    // missing migrations are applied to the copy
+    let mut connection = SqliteConnection::establish(restored_path).map_err(|e| format!("Cannot open the backup: {}", e))?;
+    let known_migrations = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)
+        .map_err(|e| format!("Cannot read the migrations: {}", e))?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect::<Vec<String>>();
+    let applied_migrations = connection.applied_migrations().map_err(|e| format!("Cannot read the migrations of the backup: {}", e))?;
+    if applied_migrations.is_empty() {
+        return Err("The backup is not a Rockpass database".to_string());
+    }
+    if let Some(unknown_migration) = applied_migrations.iter().find(|version| ! known_migrations.contains(&version.to_string())) {
+        return Err(format!("The backup has the migration {} that is unknown to this version of Rockpass", unknown_migration));
+    }
+    connection.run_pending_migrations(MIGRATIONS)
+        .map(|applied_migrations| applied_migrations.len())
+        .map_err(|e| format!("Cannot apply the migrations to the backup: {}", e))
+}
@@ -0,0 +1,235 @@
+//
+// cli.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use diesel::{self, prelude::*};
+use diesel_migrations::MigrationHarness;
+use rocket::figment::Figment;
+
+use crate::hashing::Hasher;
+use crate::models::UserSummary;
+use crate::schema::{retired_tokens, tokens, users};
+use crate::{MIGRATIONS, RockpassConfig, backup, routes, sites};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage the users
+    User {
+        #[command(subcommand)]
+        command: UserCommand
+    },
+    /// Manage the session tokens
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand
+    },
+    /// Apply the pending database migrations
+    Migrate,
+    /// Write a consistent copy of the database, also while the server is running
+    Backup {
+        path: String
+    },
+    /// Replace the database with a backup, the server must be stopped
+    Restore {
+        path: String
+    }
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Add a new user
+    Add {
+        email: String,
+        /// Password of the user, it is asked if not set
+        #[arg(long)]
+        password: Option<String>,
+        /// Make the user administrator
+        #[arg(long)]
+        admin: bool
+    },
+    /// List the users
+    List,
+    /// Delete an user with all its data
+    Delete {
+        email: String
+    },
+    /// Set a new password for an user and close its sessions
+    SetPassword {
+        email: String,
+        /// New password of the user, it is asked if not set
+        #[arg(long)]
+        password: Option<String>
+    },
+    /// Grant or revoke administration to an user
+    SetAdmin {
+        email: String,
+        /// Revoke administration instead of granting it
+        #[arg(long)]
+        revoke: bool
+    }
+}
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// Delete the expired session tokens
+    Purge {
+        /// Delete all the session tokens, logging out every user
+        #[arg(long)]
+        all: bool
+    }
+}
+
+pub fn run(command: Command, figment: &Figment) -> Result<(), String> {
+    let config = figment.extract::<RockpassConfig>().map_err(|e| format!("Cannot read the configuration: {}", e))?;
+    let database_url = figment.extract_inner::<String>("databases.rockpass.url").map_err(|e| format!("Cannot read the database location: {}", e))?;
+    if database_url == ":memory:" {
+        return Err("The database is in memory, configure the location of the database file".to_string());
+    }
+    if let Command::Restore { path } = command {
+        let applied_migrations = backup::restore(&path, &database_url)?;
+        println!("Restored backup {} applying {} migrations", path, applied_migrations);
+        return Ok(());
+    }
+    let mut connection = SqliteConnection::establish(&database_url).map_err(|e| format!("Cannot open the database {}: {}", database_url, e))?;
+    let c = &mut connection;
+    match command {
+        Command::Migrate => {
+            let applied_migrations = c.run_pending_migrations(MIGRATIONS).map_err(|e| format!("Cannot apply the migrations: {}", e))?.len();
+            sites::update_stored(c, config.strip_www).map_err(|e| format!("Cannot update the canonical sites: {}", e))?;
+            println!("Applied {} migrations", applied_migrations);
+            return Ok(());
+        },
+        Command::Backup { path } => {
+            backup::backup(c, &path).map_err(|e| format!("Cannot write the backup {}: {}", path, e))?;
+            println!("Written backup {}", path);
+            return Ok(());
+        },
+        _ => ()
+    }
+    if c.has_pending_migration(MIGRATIONS).map_err(|e| format!("Cannot check the migrations: {}", e))? {
+        return Err("The database is not up to date, run the migrate command first".to_string());
+    }
+    match command {
+        Command::User { command } => run_user(command, &config, c),
+        Command::Tokens { command } => run_tokens(command, &config, c),
+        _ => Ok(())
+    }
+}
+
+fn run_user(command: UserCommand, config: &RockpassConfig, c: &mut SqliteConnection) -> Result<(), String> {
+    match command {
+        UserCommand::Add { email, password, admin } => {
+            let hasher = Hasher::from_config(config)?;
+            let hashed_password = hasher.hash(&read_password(password)?).map_err(|_| "Cannot hash the password")?;
+            diesel::insert_into(users::table)
+                .values((users::email.eq(&email), users::password.eq(hashed_password), users::is_admin.eq(admin || config.admins.contains(&email))))
+                .execute(c)
+                .map_err(|e| format!("Cannot add user {}: {}", email, e))?;
+            println!("Added user {}", email);
+        },
+        UserCommand::List => {
+            let results = users::table
+                .order(users::id)
+                .select(UserSummary::as_select())
+                .load(c)
+                .map_err(|e| format!("Cannot list the users: {}", e))?;
+            for user in results {
+                let mut flags = Vec::new();
+                if user.is_admin { flags.push("admin") }
+                if user.disabled { flags.push("disabled") }
+                println!("{}\t{}\t{}", user.id, user.email, flags.join(","));
+            }
+        },
+        UserCommand::Delete { email } => {
+            let deleted_user_id = find_user(c, &email)?;
+            routes::delete_user(c, deleted_user_id).map_err(|e| format!("Cannot delete user {}: {}", email, e))?;
+            println!("Deleted user {}", email);
+        },
+        UserCommand::SetPassword { email, password } => {
+            let updated_user_id = find_user(c, &email)?;
+            let hasher = Hasher::from_config(config)?;
+            let hashed_password = hasher.hash(&read_password(password)?).map_err(|_| "Cannot hash the password")?;
+            c.transaction(|c| {
+                diesel::delete(tokens::table)
+                    .filter(tokens::user_id.eq(&updated_user_id))
+                    .execute(c)?;
+                routes::delete_retired_tokens(c, updated_user_id)?;
+                diesel::update(users::table.find(updated_user_id))
+                    .set((users::password.eq(hashed_password), users::generation.eq(users::generation + 1)))
+                    .execute(c)
+            }).map_err(|e| format!("Cannot set the password of user {}: {}", email, e))?;
+            println!("Password changed for user {}", email);
+        },
+        UserCommand::SetAdmin { email, revoke } => {
+            let updated_user_id = find_user(c, &email)?;
+            diesel::update(users::table.find(updated_user_id))
+                .set(users::is_admin.eq(! revoke))
+                .execute(c)
+                .map_err(|e| format!("Cannot update user {}: {}", email, e))?;
+            match revoke {
+                true => println!("User {} is not administrator anymore", email),
+                false => println!("User {} is administrator", email)
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_tokens(command: TokensCommand, config: &RockpassConfig, c: &mut SqliteConnection) -> Result<(), String> {
+    match command {
+        TokensCommand::Purge { all } => {
+            // Without the all flag only the tokens that cannot be refreshed anymore are deleted
+            let min_modification_date = match all {
+                true => Utc::now() + Duration::days(1),
+                false => Utc::now() - Duration::seconds(config.refresh_token_lifetime)
+            };
+            let deleted_tokens = c.transaction(|c| {
+                let deleted_tokens = diesel::delete(tokens::table)
+                    .filter(tokens::modified.lt(min_modification_date.format("%Y-%m-%d %H:%M:%S").to_string()))
+                    .execute(c)?;
+                // The retired tokens of the deleted sessions and the expired ones are not needed
+                diesel::delete(retired_tokens::table)
+                    .filter(diesel::dsl::not(retired_tokens::token_id.eq_any(tokens::table.select(tokens::id)))
+                        .or(retired_tokens::created.lt(min_modification_date.naive_utc())))
+                    .execute(c)?;
+                Ok::<usize, diesel::result::Error>(deleted_tokens)
+            }).map_err(|e| format!("Cannot purge the tokens: {}", e))?;
+            println!("Deleted {} tokens", deleted_tokens);
+        }
+    }
+    Ok(())
+}
+
+fn find_user(c: &mut SqliteConnection, user_email: &str) -> Result<i32, String> {
+    users::table
+        .filter(users::email.eq(user_email))
+        .select(users::id)
+        .first::<i32>(c)
+        .optional()
+        .map_err(|e| format!("Cannot search user {}: {}", user_email, e))?
+        .ok_or(format!("User {} not found", user_email))
+}
+
+fn read_password(password: Option<String>) -> Result<String, String> {
+    // Ask for the password twice if it is not set in the command line
+    if let Some(password) = password {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Password: ").map_err(|e| format!("Cannot read the password: {}", e))?;
+    let confirmation = rpassword::prompt_password("Repeat password: ").map_err(|e| format!("Cannot read the password: {}", e))?;
+    match password == confirmation {
+        true => Ok(password),
+        false => Err("Passwords do not match".to_string())
+    }
+}
@@ -0,0 +1,157 @@
+//
+// errors.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use std::fmt::Display;
+
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{Json, json};
+
+use crate::models::FieldErrors;
+
+// Detail of the failure of a request guard, so the catchers can show it
+pub struct Caught(Option<String>);
+
+#[derive(Debug)]
+pub enum RockpassError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnprocessableEntity(String),
+    // Detail and the error of each invalid field
+    Invalid(String, FieldErrors),
+    ServiceUnavailable(String),
+    // Seconds that the client must wait before trying again
+    TooManyRequests(String, i64),
+    Internal(String)
+}
+
+impl RockpassError {
+    pub fn internal(error: impl Display, detail: &str) -> RockpassError {
+        // The cause is only logged, the client gets a detail that does not reveal internals
+        error!("{}: {}", detail, error);
+        RockpassError::Internal(detail.to_string())
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            RockpassError::BadRequest(_) => Status::BadRequest,
+            RockpassError::Unauthorized(_) => Status::Unauthorized,
+            RockpassError::Forbidden(_) => Status::Forbidden,
+            RockpassError::NotFound(_) => Status::NotFound,
+            RockpassError::Conflict(_) => Status::Conflict,
+            RockpassError::PreconditionFailed(_) => Status::PreconditionFailed,
+            RockpassError::PayloadTooLarge(_) => Status::PayloadTooLarge,
+            RockpassError::UnprocessableEntity(_) | RockpassError::Invalid(_, _) => Status::UnprocessableEntity,
+            RockpassError::ServiceUnavailable(_) => Status::ServiceUnavailable,
+            RockpassError::TooManyRequests(_, _) => Status::TooManyRequests,
+            RockpassError::Internal(_) => Status::InternalServerError
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            RockpassError::BadRequest(_) => "bad_request",
+            RockpassError::Unauthorized(_) => "unauthorized",
+            RockpassError::Forbidden(_) => "forbidden",
+            RockpassError::NotFound(_) => "not_found",
+            RockpassError::Conflict(_) => "conflict",
+            RockpassError::PreconditionFailed(_) => "precondition_failed",
+            RockpassError::PayloadTooLarge(_) => "payload_too_large",
+            RockpassError::UnprocessableEntity(_) => "unprocessable_entity",
+            RockpassError::Invalid(_, _) => "invalid_fields",
+            RockpassError::ServiceUnavailable(_) => "service_unavailable",
+            RockpassError::TooManyRequests(_, _) => "too_many_requests",
+            RockpassError::Internal(_) => "internal_error"
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            RockpassError::BadRequest(detail) |
+            RockpassError::Unauthorized(detail) |
+            RockpassError::Forbidden(detail) |
+            RockpassError::NotFound(detail) |
+            RockpassError::Conflict(detail) |
+            RockpassError::PreconditionFailed(detail) |
+            RockpassError::PayloadTooLarge(detail) |
+            RockpassError::UnprocessableEntity(detail) |
+            RockpassError::Invalid(detail, _) |
+            RockpassError::ServiceUnavailable(detail) |
+            RockpassError::TooManyRequests(detail, _) |
+            RockpassError::Internal(detail) => detail
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for RockpassError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut body = json!({"detail": self.detail(), "code": self.code()});
+        if let RockpassError::Invalid(_, fields) = &self {
+            body["fields"] = json!(fields);
+        }
+        let mut response = Response::build_from(Json(body).respond_to(request)?);
+        response.status(self.status());
+        if let RockpassError::TooManyRequests(_, seconds) = self {
+            response.header(Header::new("Retry-After", seconds.to_string()));
+        }
+        response.ok()
+    }
+}
+
+pub fn remember(request: &Request<'_>, detail: String) {
+    // Only the first failure is kept, it is the one that stopped the request
+    request.local_cache(|| Caught(Some(detail)));
+}
+
+fn remembered(request: &Request<'_>, default_detail: &str) -> String {
+    request.local_cache(|| Caught(None)).0.clone().unwrap_or_else(|| default_detail.to_string())
+}
+
+#[catch(400)]
+pub fn bad_request(request: &Request<'_>) -> RockpassError {
+    RockpassError::BadRequest(remembered(request, "The request is not valid"))
+}
+
+#[catch(401)]
+pub fn unauthorized(request: &Request<'_>) -> RockpassError {
+    RockpassError::Unauthorized(remembered(request, "Authentication credentials were not provided or are not valid"))
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request<'_>) -> RockpassError {
+    RockpassError::Forbidden(remembered(request, "You do not have permission to perform this action"))
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request<'_>) -> RockpassError {
+    RockpassError::NotFound(format!("Resource {} not found", request.uri().path()))
+}
+
+#[catch(413)]
+pub fn payload_too_large(request: &Request<'_>) -> RockpassError {
+    RockpassError::PayloadTooLarge(remembered(request, "The request body is too large"))
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request<'_>) -> RockpassError {
+    RockpassError::UnprocessableEntity(remembered(request, "The request body is not valid"))
+}
+
+#[catch(500)]
+pub fn internal_error(request: &Request<'_>) -> RockpassError {
+    RockpassError::Internal(remembered(request, "Internal server error"))
+}
+
+#[catch(503)]
+pub fn service_unavailable(request: &Request<'_>) -> RockpassError {
+    RockpassError::ServiceUnavailable(remembered(request, "The service is not available, try again later"))
+}
@@ -8,7 +8,8 @@ use rocket::fairing::{Fairing, Info, Kind};
 use rocket::{http::{ContentType, Header}, Data, Request, Response};
 
 pub struct Cors;
-pub struct ForceContentType(pub ContentType);
+// The second value lists the routes where the client can still ask for other content type
+pub struct ForceContentType(pub ContentType, pub Vec<(&'static str, ContentType)>);
 
 #[rocket::async_trait]
 impl Fairing for Cors {
@@ -25,6 +26,7 @@ impl Fairing for Cors {
         response.set_header(Header::new("Access-Control-Allow-Methods", "DELETE, GET, OPTIONS, PATCH, POST, PUT"));
         response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        response.set_header(Header::new("Access-Control-Expose-Headers", "ETag"));
     }
 }
 
@@ -38,6 +40,12 @@ impl Fairing for ForceContentType {
     }
 
     async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
-        request.replace_header(Header::new("Accept", format!("{}", self.0)));
+        let path = request.uri().path();
+        let negotiated = request.accept().is_some_and(|accept| self.1.iter().any(|(route, content_type)| {
+            path == *route && accept.preferred().media_type() == content_type.media_type()
+        }));
+        if ! negotiated {
+            request.replace_header(Header::new("Accept", format!("{}", self.0)));
+        }
     }
 }
@@ -0,0 +1,93 @@
+//
+// hashing.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use argon2::password_hash::SaltString;
+use bcrypt::HashParts;
+use rand::rngs::OsRng;
+use uuid::Uuid;
+
+use crate::RockpassConfig;
+
+enum HashAlgorithm {
+    Argon2id(Params),
+    Bcrypt(u32)
+}
+
+pub struct Hasher {
+    algorithm: HashAlgorithm,
+    // Hash of a random password used to spend the same time when there is no user to check
+    dummy_hash: String
+}
+
+impl Hasher {
+    pub fn from_config(config: &RockpassConfig) -> Result<Hasher, String> {
+        let algorithm = match config.password_hasher.as_str() {
+            "argon2id" => HashAlgorithm::Argon2id(
+                Params::new(config.argon2_memory_cost, config.argon2_time_cost, config.argon2_parallelism, None)
+                    .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?
+            ),
+            "bcrypt" => match config.bcrypt_cost {
+                4..=31 => HashAlgorithm::Bcrypt(config.bcrypt_cost),
+                _ => return Err(format!("Invalid bcrypt cost {}, it must be between 4 and 31", config.bcrypt_cost))
+            },
+            _ => return Err(format!("Password hasher {} is not supported, use argon2id or bcrypt", config.password_hasher))
+        };
+        let mut hasher = Hasher { algorithm, dummy_hash: String::new() };
+        hasher.dummy_hash = hasher.hash(&Uuid::new_v4().to_string()).map_err(|_| "Cannot generate the dummy password hash")?;
+        Ok(hasher)
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, ()> {
+        // Hash the password with the configured algorithm and cost
+        match &self.algorithm {
+            HashAlgorithm::Argon2id(params) => {
+                let salt = SaltString::generate(&mut OsRng);
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|_| ())
+            },
+            HashAlgorithm::Bcrypt(cost) => bcrypt::hash(password, *cost).map_err(|_| ())
+        }
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> bool {
+        // Verify against any supported algorithm, the parameters are read from the hash itself
+        if is_bcrypt(hash) {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        } else {
+            PasswordHash::new(hash)
+                .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        }
+    }
+
+    pub fn verify_dummy(&self, password: &str) {
+        // Verify against a hash that never matches so a missing user takes as long as a wrong password
+        self.verify(password, &self.dummy_hash);
+    }
+
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        // A hash needs to be updated when it was made with other algorithm or other cost
+        match &self.algorithm {
+            HashAlgorithm::Argon2id(params) => match PasswordHash::new(hash) {
+                Ok(hash) => hash.algorithm != Algorithm::Argon2id.ident() ||
+                    hash.version != Some(Version::V0x13.into()) ||
+                    Params::try_from(&hash).is_ok_and(|hash_params| {
+                        hash_params.m_cost() != params.m_cost() ||
+                        hash_params.t_cost() != params.t_cost() ||
+                        hash_params.p_cost() != params.p_cost()
+                    }),
+                Err(_) => true
+            },
+            HashAlgorithm::Bcrypt(cost) => ! is_bcrypt(hash) || hash.parse::<HashParts>().is_ok_and(|parts| parts.get_cost() != *cost)
+        }
+    }
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
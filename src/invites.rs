@@ -0,0 +1,30 @@
+//
+// invites.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+use rand::{RngCore, rngs::OsRng};
+use sha2::{Digest, Sha256};
+
+// Length in bytes of the random part of the codes (160 bits)
+const CODE_LENGTH: usize = 20;
+
+pub fn new_code() -> String {
+    // Generate a random code easy to copy, in lowercase groups of four characters
+    let mut code = [0u8; CODE_LENGTH];
+    OsRng.fill_bytes(&mut code);
+    BASE32_NOPAD.encode(&code)
+        .to_lowercase()
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+pub fn hash_code(code: &str) -> String {
+    // Codes have enough entropy to be stored with a plain digest that can be searched
+    HEXLOWER.encode(&Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
@@ -0,0 +1,182 @@
+//
+// keys.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+use base64::{Engine, engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}};
+use diesel::{self, prelude::*};
+use ed25519_dalek::{SigningKey, pkcs8::DecodePrivateKey};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header, encode};
+use jsonwebtoken::jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse};
+use rand::{RngCore, rngs::OsRng};
+use rocket::serde::{Deserialize, Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+use crate::schema::jwt_secrets;
+
+// Length in bytes of the secret generated when there are no configured keys
+const GENERATED_SECRET_LENGTH: usize = 64;
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JwtKeyConfig {
+    kid: String,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    secret: Option<String>,
+    file: Option<String>
+}
+
+fn default_algorithm() -> String { "HS256".to_string() }
+
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    // Public key to publish, only for asymmetric algorithms
+    jwk: Option<Jwk>
+}
+
+pub struct JwtKeys {
+    current: String,
+    keys: HashMap<String, JwtKey>,
+    issuer: String,
+    audience: String
+}
+
+impl JwtKeys {
+    pub fn from_config(configs: &[JwtKeyConfig], issuer: &str, audience: &str) -> Result<JwtKeys, String> {
+        // The first key signs the new tokens, the rest only verify tokens signed before rotation
+        let current = configs.first().ok_or("There are no JWT keys configured")?.kid.clone();
+        let mut keys = HashMap::new();
+        for config in configs {
+            let key = JwtKey::from_config(config).map_err(|e| format!("Cannot load JWT key {}: {}", config.kid, e))?;
+            if keys.insert(config.kid.clone(), key).is_some() {
+                return Err(format!("JWT key {} is duplicated", config.kid));
+            }
+        }
+        Ok(JwtKeys { current, keys, issuer: issuer.to_string(), audience: audience.to_string() })
+    }
+
+    pub fn from_secret(kid: String, secret: &[u8], issuer: &str, audience: &str) -> JwtKeys {
+        let key = JwtKey {
+            algorithm: Algorithm::HS256,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None
+        };
+        JwtKeys { current: kid.clone(), keys: HashMap::from([(kid, key)]), issuer: issuer.to_string(), audience: audience.to_string() }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    pub fn jwks(&self) -> Vec<&Jwk> {
+        // Public keys of asymmetric algorithms, current key first
+        let mut jwks: Vec<&Jwk> = self.keys.values().filter_map(|key| key.jwk.as_ref()).collect();
+        jwks.sort_by_key(|jwk| jwk.common.key_id.as_deref() != Some(self.current.as_str()));
+        jwks
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        // Sign with current key and set its identifier in header
+        let key = &self.keys[&self.current];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.current.clone());
+        encode(&header, claims, &key.encoding_key)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, ()> {
+        // Verify the token with the key referenced in its header
+        let kid = decode_header(token).map_err(|_| ())?.kid.ok_or(())?;
+        let key = self.keys.get(&kid).ok_or(())?;
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+        validation.validate_nbf = true;
+        decode::<T>(token, &key.decoding_key, &validation).map_err(|_| ())
+    }
+}
+
+impl JwtKey {
+    fn from_config(config: &JwtKeyConfig) -> Result<JwtKey, String> {
+        let algorithm = Algorithm::from_str(&config.algorithm).map_err(|_| format!("unknown algorithm {}", config.algorithm))?;
+        let content = match (&config.secret, &config.file) {
+            (Some(secret), None) => secret.as_bytes().to_vec(),
+            (None, Some(file)) => fs::read(file).map_err(|e| format!("cannot read {file}: {e}"))?,
+            _ => return Err("one of secret or file must be set".to_string())
+        };
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS512 => Ok(JwtKey {
+                algorithm,
+                encoding_key: EncodingKey::from_secret(&content),
+                decoding_key: DecodingKey::from_secret(&content),
+                jwk: None
+            }),
+            Algorithm::RS256 => {
+                // Public key is derived from the private one
+                let encoding_key = EncodingKey::from_rsa_pem(&content).map_err(|e| e.to_string())?;
+                let mut jwk = Jwk::from_encoding_key(&encoding_key, algorithm).map_err(|e| e.to_string())?;
+                let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|e| e.to_string())?;
+                jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+                jwk.common.key_id = Some(config.kid.clone());
+                Ok(JwtKey { algorithm, encoding_key, decoding_key, jwk: Some(jwk) })
+            },
+            Algorithm::EdDSA => {
+                // Public key is derived from the private one
+                let encoding_key = EncodingKey::from_ed_pem(&content).map_err(|e| e.to_string())?;
+                let signing_key = SigningKey::from_pkcs8_der(encoding_key.inner()).map_err(|e| e.to_string())?;
+                let public_key = signing_key.verifying_key().to_bytes();
+                let decoding_key = DecodingKey::from_ed_der(&public_key);
+                let jwk = Jwk {
+                    common: CommonParameters {
+                        public_key_use: Some(PublicKeyUse::Signature),
+                        key_algorithm: Some(KeyAlgorithm::EdDSA),
+                        key_id: Some(config.kid.clone()),
+                        ..Default::default()
+                    },
+                    algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(public_key)
+                    })
+                };
+                Ok(JwtKey { algorithm, encoding_key, decoding_key, jwk: Some(jwk) })
+            },
+            _ => Err(format!("algorithm {} is not supported, use HS256, HS512, RS256 or EdDSA", config.algorithm))
+        }
+    }
+}
+
+pub fn stored_secret(c: &mut SqliteConnection) -> QueryResult<(String, Vec<u8>)> {
+    // Get the generated secret or create it in the first start
+    let stored = jwt_secrets::table
+        .order(jwt_secrets::created.desc())
+        .select((jwt_secrets::kid, jwt_secrets::secret))
+        .first::<(String, String)>(c)
+        .optional()?;
+    let (kid, secret) = match stored {
+        Some(stored) => stored,
+        None => {
+            let mut secret = [0u8; GENERATED_SECRET_LENGTH];
+            OsRng.fill_bytes(&mut secret);
+            let new_secret = (Uuid::new_v4().to_string(), STANDARD.encode(secret));
+            diesel::insert_into(jwt_secrets::table)
+                .values((jwt_secrets::kid.eq(&new_secret.0), jwt_secrets::secret.eq(&new_secret.1)))
+                .execute(c)?;
+            new_secret
+        }
+    };
+    Ok((kid, secret.into_bytes()))
+}
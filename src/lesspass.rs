@@ -0,0 +1,121 @@
+//
+// lesspass.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+
+use crate::models::NewPassword;
+
+const ITERATIONS: u32 = 100000;
+const ENTROPY_LENGTH: usize = 32;
+const V1_ITERATIONS: u32 = 8192;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+// Big number in big endian base 256, as LessPass takes the entropy
+struct Entropy(Vec<u8>);
+
+impl Entropy {
+    fn divmod(&mut self, divisor: usize) -> usize {
+        // Long division in place, returns the remainder
+        let mut remainder = 0;
+        for byte in self.0.iter_mut() {
+            let dividend = (remainder << 8) | *byte as usize;
+            *byte = (dividend / divisor) as u8;
+            remainder = dividend % divisor;
+        }
+        remainder
+    }
+
+    fn consume(&mut self, characters: &[char], count: usize) -> Vec<char> {
+        (0..count).map(|_| characters[self.divmod(characters.len())]).collect()
+    }
+}
+
+pub fn entropy(site: &str, login: &str, counter: i32, master_password: &str) -> [u8; ENTROPY_LENGTH] {
+    // The salt is the site, the login and the counter in hexadecimal
+    let salt = format!("{site}{login}{counter:x}");
+    let mut entropy = [0u8; ENTROPY_LENGTH];
+    pbkdf2_hmac::<Sha256>(master_password.as_bytes(), salt.as_bytes(), ITERATIONS, &mut entropy);
+    entropy
+}
+
+pub fn render(entropy: &[u8], profile: &NewPassword) -> Result<String, String> {
+    // The password is made with the characters of all the enabled sets, then one character of
+    // each set is inserted in a pseudo random position so all of them are always present
+    let character_sets = [(profile.lowercase, LOWERCASE), (profile.uppercase, UPPERCASE), (profile.digits, DIGITS), (profile.symbols, SYMBOLS)]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, characters)| characters.chars().collect::<Vec<char>>())
+        .collect::<Vec<Vec<char>>>();
+    let length = usize::try_from(profile.length).map_err(|_| "The length is not valid".to_string())?;
+    if character_sets.is_empty() || length <= character_sets.len() {
+        return Err("The profile cannot be rendered".to_string());
+    }
+    let mut entropy = Entropy(entropy.to_vec());
+    let mut password = entropy.consume(&character_sets.concat(), length - character_sets.len());
+    let one_per_set = character_sets.iter()
+        .flat_map(|characters| entropy.consume(characters, 1))
+        .collect::<Vec<char>>();
+    for character in one_per_set {
+        let position = entropy.divmod(password.len());
+        password.insert(position, character);
+    }
+    Ok(password.into_iter().collect())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn encrypted_login(login: &str, master_password: &str) -> String {
+    // Version 1 derives the key only from the login, the site is added later
+    let mut encrypted_login = [0u8; ENTROPY_LENGTH];
+    pbkdf2_hmac::<Sha256>(master_password.as_bytes(), login.as_bytes(), V1_ITERATIONS, &mut encrypted_login);
+    hex(&encrypted_login)
+}
+
+pub fn render_v1(encrypted_login: &str, profile: &NewPassword) -> Result<String, String> {
+    // The hexadecimal HMAC of the site and the counter is cut to the length and each of its
+    // characters is mapped to a character of the template, that alternates vowels and
+    // consonants for the letters
+    let template = [(profile.lowercase, "vc"), (profile.uppercase, "VC"), (profile.digits, "n"), (profile.symbols, "s")]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, template)| template)
+        .collect::<String>()
+        .into_bytes();
+    let length = usize::try_from(profile.length).map_err(|_| "The length is not valid".to_string())?;
+    if template.is_empty() || length > ENTROPY_LENGTH * 2 {
+        return Err("The profile cannot be rendered".to_string());
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(encrypted_login.as_bytes()).map_err(|e| e.to_string())?;
+    mac.update(format!("{}{}", profile.site, profile.counter).as_bytes());
+    let derived_login = hex(&mac.finalize().into_bytes());
+    Ok(derived_login.bytes().take(length).enumerate().map(|(position, code)| {
+        let characters = match template[position % template.len()] {
+            b'V' => "AEIOUY",
+            b'C' => "BCDFGHJKLMNPQRSTVWXZ",
+            b'v' => "aeiouy",
+            b'c' => "bcdfghjklmnpqrstvwxz",
+            b'n' => "0123456789",
+            _ => "@&%?,=[]_:-+*$#!'^~;()/."
+        }.as_bytes();
+        characters[code as usize % characters.len()] as char
+    }).collect())
+}
+
+pub fn generate(profile: &NewPassword, master_password: &str) -> Result<String, String> {
+    match profile.version {
+        1 => render_v1(&encrypted_login(&profile.login, master_password), profile),
+        2 => render(&entropy(&profile.site, &profile.login, profile.counter, master_password), profile),
+        version => Err(format!("Passwords of version {version} cannot be rendered"))
+    }
+}
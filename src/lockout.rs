@@ -0,0 +1,116 @@
+//
+// lockout.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+
+use crate::RockpassConfig;
+use crate::schema::login_failures;
+
+// Failures older than this are forgotten and the counter starts again
+const FAILURES_WINDOW: i64 = 86400;
+
+pub const ACCOUNT: &str = "account";
+pub const IP: &str = "ip";
+
+pub struct Backoff {
+    account_threshold: i32,
+    ip_threshold: i32,
+    base: i64,
+    max: i64
+}
+
+impl Backoff {
+    pub fn from_config(config: &RockpassConfig) -> Backoff {
+        Backoff {
+            account_threshold: config.login_failures_threshold,
+            ip_threshold: config.login_ip_failures_threshold,
+            base: config.login_backoff_base,
+            max: config.login_backoff_max
+        }
+    }
+}
+
+pub fn subjects(user_email: &str, client_ip: Option<&str>) -> Vec<(&'static str, String)> {
+    // Failures are counted both for the account and for the client IP
+    let mut subjects = vec![(ACCOUNT, user_email.trim().to_lowercase())];
+    if let Some(client_ip) = client_ip {
+        subjects.push((IP, client_ip.to_string()));
+    }
+    subjects
+}
+
+pub fn retry_after(c: &mut SqliteConnection, subjects: &[(&'static str, String)]) -> QueryResult<Option<i64>> {
+    // Return the seconds to wait if any of the subjects is locked
+    let now = Utc::now().naive_utc();
+    let mut locked_until: Option<NaiveDateTime> = None;
+    for (scope, subject) in subjects {
+        let subject_locked_until = login_failures::table
+            .find((scope, subject))
+            .select(login_failures::locked_until)
+            .first::<Option<NaiveDateTime>>(c)
+            .optional()?
+            .flatten()
+            .filter(|subject_locked_until| *subject_locked_until > now);
+        locked_until = locked_until.max(subject_locked_until);
+    }
+    Ok(locked_until.map(|locked_until| (locked_until - now).num_seconds().max(1)))
+}
+
+pub fn record_failure(c: &mut SqliteConnection, backoff: &Backoff, subjects: &[(&'static str, String)]) -> QueryResult<()> {
+    // Increase the failure counters and lock the subjects that reach their threshold, each new
+    // failure after it doubles the time that the subject is locked
+    let now = Utc::now().naive_utc();
+    c.transaction(|c| {
+        // The failures out of the window that are not locking anything are forgotten, so the
+        // table does not grow with every subject that ever failed
+        diesel::delete(login_failures::table)
+            .filter(login_failures::modified.lt(now - Duration::seconds(FAILURES_WINDOW)))
+            .filter(login_failures::locked_until.is_null().or(login_failures::locked_until.lt(now)))
+            .execute(c)?;
+        for (scope, subject) in subjects {
+            let threshold = match *scope {
+                ACCOUNT => backoff.account_threshold,
+                _ => backoff.ip_threshold
+            };
+            if threshold == 0 {
+                continue;
+            }
+            let previous = login_failures::table
+                .find((scope, subject))
+                .select((login_failures::failures, login_failures::modified))
+                .first::<(i32, NaiveDateTime)>(c)
+                .optional()?;
+            let failures = match previous {
+                Some((failures, modified)) if modified > now - Duration::seconds(FAILURES_WINDOW) => failures + 1,
+                _ => 1
+            };
+            let locked_until = match failures >= threshold {
+                true => {
+                    let exponent = (failures - threshold).min(62) as u32;
+                    let delay = backoff.base.saturating_mul(2i64.saturating_pow(exponent)).min(backoff.max);
+                    Some(now + Duration::seconds(delay))
+                },
+                false => None
+            };
+            diesel::replace_into(login_failures::table)
+                .values((
+                    login_failures::scope.eq(scope),
+                    login_failures::subject.eq(subject),
+                    login_failures::failures.eq(failures),
+                    login_failures::locked_until.eq(locked_until),
+                    login_failures::modified.eq(now)
+                ))
+                .execute(c)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn reset(c: &mut SqliteConnection, scope: &str, subject: &str) -> QueryResult<usize> {
+    // Forget the failures of a subject and unlock it
+    diesel::delete(login_failures::table.find((scope, subject))).execute(c)
+}
@@ -4,15 +4,19 @@
 // Distributed under terms of the GNU GPLv3 license.
 //
 
+use std::collections::BTreeMap;
+
 use rocket::serde::{Serialize, Deserialize, Deserializer};
 use chrono::NaiveDateTime;
 
-use crate::schema::passwords;
+use crate::schema::{invitations, passwords, recovery_codes, retired_tokens, totp_secrets, users};
 
 pub struct AuthorizedUser {
     pub id: i32,
     pub email: String,
     pub password: String,
+    pub generation: i32,
+    pub is_admin: bool,
     pub token_id: i32
 }
 
@@ -21,14 +25,38 @@ pub struct AuthorizedUser {
 pub struct User {
     pub id: i32,
     pub email: String,
-    pub password: String
+    pub password: String,
+    pub generation: i32,
+    pub is_admin: bool,
+    pub disabled: bool
+}
+
+#[derive(Serialize, Queryable, Selectable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = users)]
+pub struct UserSummary {
+    pub id: i32,
+    pub email: String,
+    pub is_admin: bool,
+    pub disabled: bool
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct NewUser {
     pub email: String,
-    pub password: String
+    pub password: String,
+    // Invitation code, allows to register even when registration is disabled
+    pub invite: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserLogin {
+    pub email: String,
+    pub password: String,
+    // One-time code or recovery code, only required if user has two-factor authentication enabled
+    pub otp: Option<String>
 }
 
 #[derive(Deserialize)]
@@ -44,6 +72,18 @@ pub struct NewUserPassword {
     pub new_password: String
 }
 
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MasterPassword {
+    pub master_password: String
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ResetUserPassword {
+    pub new_password: String
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct JWTRefreshToken {
@@ -58,7 +98,65 @@ pub struct DBToken {
     pub access_token: String,
     pub refresh_token: String,
     pub created: NaiveDateTime,
-    pub modified: NaiveDateTime
+    pub modified: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = retired_tokens)]
+pub struct RetiredToken {
+    pub token_id: i32,
+    pub user_id: i32,
+    pub created: NaiveDateTime
+}
+
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = totp_secrets)]
+pub struct TotpSecret {
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_used_step: Option<i64>
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpCode {
+    pub code: String
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = recovery_codes)]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub code: String
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewInvitation {
+    #[serde(default = "default_max_uses")]
+    pub max_uses: i32,
+    // Seconds until the invitation expires, if not set the configured lifetime is used
+    pub lifetime: Option<i64>
+}
+
+fn default_max_uses() -> i32 { 1 }
+
+#[derive(Serialize, Queryable, Selectable)]
+#[serde(crate = "rocket::serde")]
+pub struct Invitation {
+    pub id: i32,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires: NaiveDateTime,
+    pub created_by: Option<i32>,
+    pub created: NaiveDateTime
 }
 
 #[derive(Serialize, Deserialize, Queryable, Selectable)]
@@ -76,7 +174,13 @@ pub struct Password {
     pub version: i32,
     pub length: i32,
     pub created: NaiveDateTime,
-    pub modified: NaiveDateTime
+    pub modified: NaiveDateTime,
+    // The revision is sent as the ETag of the password and kept out of the LessPass shape
+    #[serde(skip)]
+    pub revision: i32,
+    // Only used to look up the passwords of a site
+    #[serde(skip)]
+    pub site_canonical: String
 }
 
 // Older versions of LessPass send `numbers` instead of `digits`, other versions can even send both
@@ -111,3 +215,213 @@ pub struct NewPassword {
 }
 
 const fn default_version() -> i32 { 2 }
+
+// Limits of the profiles that LessPass clients are able to use
+pub const MAX_TEXT_LENGTH: usize = 255;
+pub const MIN_PASSWORD_LENGTH: i32 = 5;
+pub const MAX_PASSWORD_LENGTH: i32 = 35;
+// Version 1 passwords are cut from a SHA-256 hexadecimal digest
+pub const MIN_V1_PASSWORD_LENGTH: i32 = 6;
+pub const MAX_V1_PASSWORD_LENGTH: i32 = 64;
+pub const VERSIONS: [i32; 2] = [1, 2];
+
+// Error messages of the invalid fields, by field name
+pub type FieldErrors = BTreeMap<&'static str, String>;
+
+fn validate_text(errors: &mut FieldErrors, field: &'static str, value: &str) {
+    if value.trim().is_empty() {
+        errors.insert(field, format!("The {field} cannot be empty"));
+    } else if value.chars().count() > MAX_TEXT_LENGTH {
+        errors.insert(field, format!("The {field} cannot be longer than {MAX_TEXT_LENGTH} characters"));
+    }
+}
+
+impl NewPassword {
+    pub fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        validate_text(&mut errors, "login", &self.login);
+        validate_text(&mut errors, "site", &self.site);
+        if ! (self.uppercase || self.symbols || self.lowercase || self.digits) {
+            errors.insert("characters", "At least one of uppercase, symbols, lowercase or digits must be enabled".to_string());
+        }
+        if self.counter < 1 {
+            errors.insert("counter", "The counter must be 1 or greater".to_string());
+        }
+        if ! VERSIONS.contains(&self.version) {
+            errors.insert("version", format!("The version must be one of {VERSIONS:?}"));
+        }
+        let (min_length, max_length) = match self.version {
+            1 => (MIN_V1_PASSWORD_LENGTH, MAX_V1_PASSWORD_LENGTH),
+            _ => (MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH)
+        };
+        if ! (min_length..=max_length).contains(&self.length) {
+            errors.insert("length", format!("The length must be between {min_length} and {max_length}"));
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub fn changed(&self, changes: &PasswordChanges) -> NewPassword {
+        // Profile that results of applying the changes, to validate it before storing them
+        NewPassword {
+            login: changes.login.clone().unwrap_or_else(|| self.login.clone()),
+            site: changes.site.clone().unwrap_or_else(|| self.site.clone()),
+            uppercase: changes.uppercase.unwrap_or(self.uppercase),
+            symbols: changes.symbols.unwrap_or(self.symbols),
+            lowercase: changes.lowercase.unwrap_or(self.lowercase),
+            digits: changes.digits.unwrap_or(self.digits),
+            counter: changes.counter.unwrap_or(self.counter),
+            version: changes.version.unwrap_or(self.version),
+            length: changes.length.unwrap_or(self.length)
+        }
+    }
+}
+
+impl From<Password> for NewPassword {
+    fn from(stored_password: Password) -> NewPassword {
+        NewPassword {
+            login: stored_password.login,
+            site: stored_password.site,
+            uppercase: stored_password.uppercase,
+            symbols: stored_password.symbols,
+            lowercase: stored_password.lowercase,
+            digits: stored_password.digits,
+            counter: stored_password.counter,
+            version: stored_password.version,
+            length: stored_password.length
+        }
+    }
+}
+
+fn optional_digits_or_numbers<'d, D: Deserializer<'d>>(d: D) -> Result<Option<bool>, D::Error> {
+    let DigitsOrNumbers { digits, numbers } = DigitsOrNumbers::deserialize(d)?;
+    Ok(digits.or(numbers))
+}
+
+// Partial update of a password, only the fields that are present are changed
+#[derive(Deserialize, AsChangeset)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = passwords)]
+pub struct PasswordChanges {
+    pub login: Option<String>,
+    pub site: Option<String>,
+    pub uppercase: Option<bool>,
+    pub symbols: Option<bool>,
+    pub lowercase: Option<bool>,
+    #[serde(deserialize_with = "optional_digits_or_numbers", flatten)]
+    pub digits: Option<bool>,
+    pub counter: Option<i32>,
+    pub version: Option<i32>,
+    pub length: Option<i32>
+}
+
+impl PasswordChanges {
+    pub fn is_empty(&self) -> bool {
+        self.login.is_none() && self.site.is_none() && self.uppercase.is_none() && self.symbols.is_none() &&
+            self.lowercase.is_none() && self.digits.is_none() && self.counter.is_none() && self.version.is_none() &&
+            self.length.is_none()
+    }
+}
+
+#[derive(FromForm)]
+pub struct PasswordsQuery {
+    pub search: Option<String>,
+    pub format: Option<String>,
+    pub login: Option<String>,
+    pub site: Option<String>,
+    pub domain: Option<String>,
+    pub ordering: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+// The CSV columns are matched by name, so the extra ones of the listing are ignored and the
+// legacy `numbers` column is accepted instead of `digits`. Booleans can be written as
+// spreadsheets do.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CsvPassword {
+    login: String,
+    site: String,
+    #[serde(deserialize_with = "spreadsheet_bool")]
+    uppercase: bool,
+    #[serde(deserialize_with = "spreadsheet_bool")]
+    symbols: bool,
+    #[serde(deserialize_with = "spreadsheet_bool")]
+    lowercase: bool,
+    #[serde(default, deserialize_with = "optional_spreadsheet_bool")]
+    digits: Option<bool>,
+    #[serde(default, deserialize_with = "optional_spreadsheet_bool")]
+    numbers: Option<bool>,
+    counter: i32,
+    #[serde(default = "default_version")]
+    version: i32,
+    length: i32
+}
+
+fn spreadsheet_bool<'d, D: Deserializer<'d>>(d: D) -> Result<bool, D::Error> {
+    optional_spreadsheet_bool(d)?.ok_or(rocket::serde::de::Error::custom("empty boolean"))
+}
+
+fn optional_spreadsheet_bool<'d, D: Deserializer<'d>>(d: D) -> Result<Option<bool>, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.trim().to_lowercase().as_str() {
+        "" => Ok(None),
+        "true" | "1" | "yes" => Ok(Some(true)),
+        "false" | "0" | "no" => Ok(Some(false)),
+        _ => Err(rocket::serde::de::Error::custom(format!("invalid boolean {value}")))
+    }
+}
+
+// Spreadsheets run the cells that start with these characters as formulas, so the exported
+// values that start with them get a quote in front that the import removes. The values that
+// already start with a quote get another one to be imported unchanged.
+const FORMULA_PREFIXES: [char; 7] = ['=', '+', '-', '@', '\t', '\r', '\''];
+
+pub fn escape_formula(value: &str) -> String {
+    match value.starts_with(FORMULA_PREFIXES) {
+        true => format!("'{value}"),
+        false => value.to_string()
+    }
+}
+
+fn unescape_formula(value: String) -> String {
+    match value.strip_prefix('\'') {
+        Some(formula) if formula.starts_with(FORMULA_PREFIXES) => formula.to_string(),
+        _ => value
+    }
+}
+
+impl TryFrom<CsvPassword> for NewPassword {
+    type Error = &'static str;
+
+    fn try_from(csv_password: CsvPassword) -> Result<NewPassword, &'static str> {
+        Ok(NewPassword {
+            login: unescape_formula(csv_password.login),
+            site: unescape_formula(csv_password.site),
+            uppercase: csv_password.uppercase,
+            symbols: csv_password.symbols,
+            lowercase: csv_password.lowercase,
+            digits: csv_password.digits.or(csv_password.numbers).ok_or("missing field `digits` or `numbers`")?,
+            counter: csv_password.counter,
+            version: csv_password.version,
+            length: csv_password.length
+        })
+    }
+}
+
+// The import takes a plain list of profiles or the same object returned by the export
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum PasswordsImport {
+    Profiles(Vec<NewPassword>),
+    Export { results: Vec<NewPassword> }
+}
+
+impl PasswordsImport {
+    pub fn into_profiles(self) -> Vec<NewPassword> {
+        match self {
+            PasswordsImport::Profiles(profiles) => profiles,
+            PasswordsImport::Export { results } => results
+        }
+    }
+}
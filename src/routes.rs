@@ -4,42 +4,142 @@
 // Distributed under terms of the GNU GPLv3 license.
 //
 
-use bcrypt::{hash, verify};
+use std::net::IpAddr;
+use std::ops::Deref;
+
 use chrono::Duration;
 use chrono::prelude::*;
-use diesel::{self, prelude::*};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode, dangerous::insecure_decode};
-use rocket::State;
-use rocket::http::Status;
+use diesel::{self, prelude::*, result::{DatabaseErrorKind, Error::DatabaseError}, sqlite::Sqlite};
+use jsonwebtoken::dangerous::insecure_decode;
+use rocket::{Data, State};
+use rocket::data::{self, FromData, Limits, ToByteUnit};
+use rocket::tokio::fs::File;
+use rocket::http::{Accept, Header, Status, uri::Origin};
 use rocket::request::{Outcome, Request, FromRequest};
 use rocket::response::status;
-use rocket::serde::{Serialize, Deserialize, json::{Json, Value, json}};
+use rocket::serde::{Serialize, Deserialize, json::{self, Json, Value, json}};
 use uuid::Uuid;
 
-use crate::models::{AuthorizedUser, NewUser, NewUserPassword, User, UserPassword, JWTRefreshToken, DBToken, NewPassword, Password};
-use crate::{RockpassDatabase, RockpassConfig};
+use crate::models::{MAX_PASSWORD_LENGTH, AuthorizedUser, ClientInfo, Invitation, MasterPassword, NewInvitation, NewUser, NewUserPassword, ResetUserPassword, User, UserSummary, UserLogin, UserPassword, JWTRefreshToken, DBToken, RetiredToken, TotpSecret, TotpCode, RecoveryCode, NewPassword, CsvPassword, Password, PasswordChanges, PasswordsImport, PasswordsQuery, escape_formula};
+use crate::errors::{self, RockpassError};
+use crate::hashing::Hasher;
+use crate::keys::JwtKeys;
+use crate::{RockpassDatabase, RockpassConfig, backup, invites, lesspass, lockout, totp};
+use crate::sites::Sites;
 use crate::schema::passwords::dsl::*;
 use crate::schema::tokens::dsl::*;
 use crate::schema::users::dsl::*;
-use crate::schema::{passwords, tokens, users};
+use crate::schema::{deleted_passwords, invitations, passwords, recovery_codes, retired_tokens, tokens, totp_secrets, users};
 
-// Define bcrypt cost for password
-const BCRYPT_COST: u32 = 12;
+// Largest page of passwords that can be asked for
+const MAX_PAGE_LIMIT: i64 = 1000;
 
 pub struct Authorization(RockpassDatabase, AuthorizedUser);
 
+pub struct Admin(RockpassDatabase, AuthorizedUser);
+
+#[derive(Responder)]
+pub struct Tagged(status::Custom<Json<Value>>, Header<'static>);
+
+#[derive(Responder)]
+pub enum Listing {
+    Json(status::Custom<Json<Value>>),
+    Csv(Csv)
+}
+
+#[derive(Responder)]
+#[response(status = 200, content_type = "text/csv")]
+pub struct Csv(String);
+
+#[derive(Responder)]
+#[response(status = 200, content_type = "application/vnd.sqlite3")]
+pub struct Backup(File, Header<'static>);
+
+// Value of the If-Match header, the request is not conditional without it
+pub struct IfMatch(Option<String>);
+
+// JSON body that keeps the reason why it cannot be parsed, the catchers show it to the client
+pub struct JsonBody<T>(pub T);
+
 #[derive(Debug)]
 pub enum AuthorizationError {
     Missing,
     Invalid,
-    Unauthorized
+    Unauthorized,
+    Forbidden,
+    Unavailable
+}
+
+impl AuthorizationError {
+    fn detail(&self) -> &'static str {
+        match self {
+            AuthorizationError::Missing => "Authentication credentials were not provided",
+            AuthorizationError::Invalid => "Authorization header must contain a bearer token",
+            AuthorizationError::Unauthorized => "The access token is not valid or has expired",
+            AuthorizationError::Forbidden => "You do not have permission to perform this action",
+            AuthorizationError::Unavailable => "The database is not available, try again later"
+        }
+    }
+}
+
+#[derive(Debug)]
+enum LoginError {
+    Credentials,
+    MissingCode,
+    InvalidCode,
+    Unavailable
+}
+
+impl LoginError {
+    fn detail(&self) -> &'static str {
+        match self {
+            LoginError::Credentials => "No active account found with the given credentials",
+            LoginError::MissingCode => "Two-factor authentication code required",
+            LoginError::InvalidCode => "Invalid two-factor authentication code",
+            LoginError::Unavailable => "There was a problem checking the two-factor authentication"
+        }
+    }
+}
+
+fn rejected<T>(request: &Request<'_>, error_status: Status, error: AuthorizationError) -> Outcome<T, AuthorizationError> {
+    errors::remember(request, error.detail().to_string());
+    Outcome::Error((error_status, error))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct Claims {
     uuid: String,
-    exp: usize
+    sub: String,
+    iss: String,
+    aud: String,
+    iat: usize,
+    nbf: usize,
+    exp: usize,
+    // User generation when the token was signed, it changes with each password change
+    #[serde(default)]
+    generation: i32
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfMatch(request.headers().get_one("If-Match").map(|tags| tags.to_string())))
+    }
+}
+
+impl IfMatch {
+    pub fn revisions(&self) -> Option<Vec<i32>> {
+        // Revisions accepted by the request, None if any revision is fine. The ETags are compared
+        // strongly so the weak ones never match
+        let tags = self.0.as_deref()?.split(',').map(str::trim).collect::<Vec<&str>>();
+        match tags.contains(&"*") {
+            true => None,
+            false => Some(tags.iter().filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()).collect())
+        }
+    }
 }
 
 #[rocket::async_trait]
@@ -49,26 +149,94 @@ impl<'r> FromRequest<'r> for Authorization {
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         // Seek for authorization header
         match request.headers().get_one("authorization") {
-            None => Outcome::Error((Status::BadRequest, AuthorizationError::Missing)),
+            None => rejected(request, Status::BadRequest, AuthorizationError::Missing),
             Some(auth) => {
                 // Authorization must start with 'bearer'
                 if (auth.len() > 7) && (&auth[..6].to_lowercase()) == "bearer" {
-                    // Get database connection
-                    let connection = request.guard::<RockpassDatabase>().await.expect("database connection");
+                    // Get database connection and JWT keys
+                    let (Outcome::Success(connection), Some(jwt_keys)) = (request.guard::<RockpassDatabase>().await, request.rocket().state::<JwtKeys>()) else {
+                        return rejected(request, Status::ServiceUnavailable, AuthorizationError::Unavailable);
+                    };
                     // Check the autorization token (remove 'bearer' and pass JWT token only)
-                    match check_authorization(&connection, &auth[7..]).await {
+                    match check_authorization(&connection, jwt_keys, &auth[7..]).await {
                         Ok(authorized_user) => Outcome::Success(Authorization(connection, authorized_user)),
-                        Err(_) => Outcome::Error((Status::Unauthorized, AuthorizationError::Unauthorized))
+                        Err(_) => rejected(request, Status::Unauthorized, AuthorizationError::Unauthorized)
                     }
                 } else {
-                    Outcome::Error((Status::BadRequest, AuthorizationError::Invalid))
+                    rejected(request, Status::BadRequest, AuthorizationError::Invalid)
                 }
             }
         }
     }
 }
 
-async fn check_authorization(connection: &RockpassDatabase, authorization_access_token: &str) -> Result<AuthorizedUser, ()> {
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = AuthorizationError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Authorize the user as usual and then check that it is an administrator
+        match request.guard::<Authorization>().await {
+            Outcome::Success(Authorization(connection, authorized_user)) if authorized_user.is_admin => Outcome::Success(Admin(connection, authorized_user)),
+            Outcome::Success(_) => rejected(request, Status::Forbidden, AuthorizationError::Forbidden),
+            Outcome::Error(error) => Outcome::Error(error),
+            Outcome::Forward(status) => Outcome::Forward(status)
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r>> FromData<'r> for JsonBody<T> {
+    type Error = json::Error<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        // Parse it as Json does and remember the error, the serde one says which field is wrong
+        match <Json<T> as FromData>::from_data(request, data).await {
+            data::Outcome::Success(body) => data::Outcome::Success(JsonBody(body.into_inner())),
+            data::Outcome::Error((error_status, error)) => {
+                let detail = match &error {
+                    json::Error::Parse(_, e) => e.to_string(),
+                    json::Error::Io(_) if error_status == Status::PayloadTooLarge => "The request body is too large".to_string(),
+                    json::Error::Io(_) => "The request body cannot be read".to_string()
+                };
+                errors::remember(request, detail);
+                data::Outcome::Error((error_status, error))
+            },
+            data::Outcome::Forward(forward) => data::Outcome::Forward(forward)
+        }
+    }
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Get user agent and client IP to identify the session. Anyone can send the IP header,
+        // so it is only taken from the configured trusted proxies
+        let remote_ip = request.remote().map(|remote| remote.ip());
+        let trusted_proxy = remote_ip.is_some_and(|remote_ip| request.rocket().state::<RockpassConfig>()
+            .is_some_and(|config| config.trusted_proxies.iter().any(|proxy| proxy.parse() == Ok(remote_ip))));
+        let client_ip = match trusted_proxy {
+            true => request.client_ip(),
+            false => remote_ip
+        };
+        Outcome::Success(ClientInfo {
+            user_agent: request.headers().get_one("user-agent").map(|header| header.to_string()),
+            ip: client_ip.map(|address| address.to_string())
+        })
+    }
+}
+
+async fn check_authorization(connection: &RockpassDatabase, jwt_keys: &JwtKeys, authorization_access_token: &str) -> Result<AuthorizedUser, ()> {
     // Get UUID value from authorization token
     let uuid = get_uuid_from_token(authorization_access_token)?;
     // Seek for UUID field in database
@@ -79,13 +247,13 @@ async fn check_authorization(connection: &RockpassDatabase, authorization_access
     }).await {
         Ok(tokens_vector) => {
             // Validate token and get authorized user
-            get_authorized_user(connection, &tokens_vector, authorization_access_token).await
+            get_authorized_user(connection, jwt_keys, &tokens_vector, authorization_access_token).await
         },
         Err(_) => Err(())
     }
 }
 
-async fn check_refresh(connection: &RockpassDatabase, payload_refresh_token: &str) -> Result<AuthorizedUser, ()> {
+async fn check_refresh(connection: &RockpassDatabase, jwt_keys: &JwtKeys, payload_refresh_token: &str) -> Result<AuthorizedUser, ()> {
     // Get UUID value from refresh token
     let uuid = get_uuid_from_token(payload_refresh_token)?;
     // Seek for UUID field in database
@@ -96,13 +264,13 @@ async fn check_refresh(connection: &RockpassDatabase, payload_refresh_token: &st
     }).await {
         Ok(tokens_vector) => {
             // Validate token and get authorized user
-            get_authorized_user(connection, &tokens_vector, payload_refresh_token).await
+            get_authorized_user(connection, jwt_keys, &tokens_vector, payload_refresh_token).await
         },
         Err(_) => Err(())
     }
 }
 
-async fn get_authorized_user(connection: &RockpassDatabase, tokens_vector: &[DBToken], token: &str) -> Result<AuthorizedUser, ()> {
+async fn get_authorized_user(connection: &RockpassDatabase, jwt_keys: &JwtKeys, tokens_vector: &[DBToken], token: &str) -> Result<AuthorizedUser, ()> {
     if tokens_vector.is_empty() {
         // No UUID token found
         return Err(());
@@ -112,15 +280,18 @@ async fn get_authorized_user(connection: &RockpassDatabase, tokens_vector: &[DBT
     match connection.run(move |c| {
         users::table
             .find(&token_user_id)
+            .filter(disabled.eq(false))
             .first::<User>(c)
     }).await {
         Ok(users_vector) => {
-            // Check JWT token with shared key and return authorized user
-            check_jwt(&users_vector.password, &token.to_string()).
+            // Check JWT token with server keys and return authorized user
+            check_jwt(jwt_keys, users_vector.id, users_vector.generation, token).
                 map(|_| Ok(AuthorizedUser {
                     id: users_vector.id,
                     email: users_vector.email,
                     password: users_vector.password,
+                    generation: users_vector.generation,
+                    is_admin: users_vector.is_admin,
                     token_id: tokens_vector[0].id
                 }))?
         },
@@ -134,43 +305,46 @@ fn get_uuid_from_token(token: &str) -> Result<String, ()> {
     Ok(token_data.claims.uuid)
 }
 
-fn new_jwt(shared_key: &String, uuid: &String, token_lifetime: &i64) -> Result<String, jsonwebtoken::errors::Error> {
-    // Calculate expiration date
-    let expiration_date = Utc::now() + Duration::seconds(*token_lifetime);
+fn new_jwt(jwt_keys: &JwtKeys, uuid: &String, user_identifier: i32, user_generation: i32, token_lifetime: &i64) -> Result<String, jsonwebtoken::errors::Error> {
+    // Calculate issue and expiration dates
+    let issue_date = Utc::now();
+    let expiration_date = issue_date + Duration::seconds(*token_lifetime);
     // Insert data in claims
     let claims = Claims {
         uuid: uuid.to_owned(),
-        exp: expiration_date.timestamp() as usize
+        sub: user_identifier.to_string(),
+        iss: jwt_keys.issuer().to_string(),
+        aud: jwt_keys.audience().to_string(),
+        iat: issue_date.timestamp() as usize,
+        nbf: issue_date.timestamp() as usize,
+        exp: expiration_date.timestamp() as usize,
+        generation: user_generation
     };
     // Return new JWT token
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(shared_key.as_ref())
-    )
+    jwt_keys.encode(&claims)
 }
 
-fn check_jwt(shared_key: &String, jwt_token: &String) -> Result<(), ()> {
-    // Verify token with shared key
-    decode::<Claims>(
-        jwt_token,
-        &DecodingKey::from_secret(shared_key.as_ref()),
-        &Validation::default(),
-    ).map(|_| ()).map_err(|_| ())
+fn check_jwt(jwt_keys: &JwtKeys, user_identifier: i32, user_generation: i32, jwt_token: &str) -> Result<(), ()> {
+    // Verify token with server keys and check that it belongs to current user generation
+    let token_data = jwt_keys.decode::<Claims>(jwt_token)?;
+    match token_data.claims.sub == user_identifier.to_string() && token_data.claims.generation == user_generation {
+        true => Ok(()),
+        false => Err(())
+    }
 }
 
-async fn create_tokens(connection: &RockpassDatabase, user: &User, access_token_lifetime: &i64, refresh_token_lifetime: &i64) -> Result<(String, String), ()> {
+async fn create_tokens(connection: &RockpassDatabase, jwt_keys: &JwtKeys, user: &User, client_info: ClientInfo, access_token_lifetime: &i64, refresh_token_lifetime: &i64) -> Result<(String, String), ()> {
     // Make new UUIDs for access and refresh tokens
     let access_token_uuid = Uuid::new_v4().to_string();
     let refresh_token_uuid = Uuid::new_v4().to_string();
     // Calculate new JWT tokens
-    let access_token_jwt = new_jwt(&user.password, &access_token_uuid, access_token_lifetime).map_err(|_| ())?;
-    let refresh_token_jwt = new_jwt(&user.password, &refresh_token_uuid, refresh_token_lifetime).map_err(|_| ())?;
+    let access_token_jwt = new_jwt(jwt_keys, &access_token_uuid, user.id, user.generation, access_token_lifetime).map_err(|_| ())?;
+    let refresh_token_jwt = new_jwt(jwt_keys, &refresh_token_uuid, user.id, user.generation, refresh_token_lifetime).map_err(|_| ())?;
     // Insert it into database
     let token_user_id = user.id;
     match connection.run(move |c| {
         diesel::insert_into(tokens::table)
-            .values((tokens::user_id.eq(&token_user_id), access_token.eq(&access_token_uuid), refresh_token.eq(&refresh_token_uuid)))
+            .values((tokens::user_id.eq(&token_user_id), access_token.eq(&access_token_uuid), refresh_token.eq(&refresh_token_uuid), tokens::user_agent.eq(&client_info.user_agent), tokens::ip.eq(&client_info.ip)))
             .execute(c)
     }).await {
         Ok(rows) => {
@@ -184,54 +358,201 @@ async fn create_tokens(connection: &RockpassDatabase, user: &User, access_token_
     }
 }
 
-async fn refresh_tokens(connection: &RockpassDatabase, authorized_user: &AuthorizedUser, access_token_lifetime: &i64, refresh_token_lifetime: &i64) -> Result<(String, String), ()> {
+pub async fn refresh_tokens(connection: &RockpassDatabase, jwt_keys: &JwtKeys, authorized_user: &AuthorizedUser, payload_refresh_token: &str, access_token_lifetime: &i64, refresh_token_lifetime: &i64) -> Result<Option<(String, String)>, ()> {
+    // Get UUID value from the refresh token being replaced
+    let presented_refresh_token = get_uuid_from_token(payload_refresh_token)?;
     // Make new UUIDs for access and refresh tokens
     let access_token_uuid = Uuid::new_v4().to_string();
     let refresh_token_uuid = Uuid::new_v4().to_string();
     // Calculate new JWT tokens
-    let access_token_jwt = new_jwt(&authorized_user.password, &access_token_uuid, access_token_lifetime).map_err(|_| ())?;
-    let refresh_token_jwt = new_jwt(&authorized_user.password, &refresh_token_uuid, refresh_token_lifetime).map_err(|_| ())?;
+    let access_token_jwt = new_jwt(jwt_keys, &access_token_uuid, authorized_user.id, authorized_user.generation, access_token_lifetime).map_err(|_| ())?;
+    let refresh_token_jwt = new_jwt(jwt_keys, &refresh_token_uuid, authorized_user.id, authorized_user.generation, refresh_token_lifetime).map_err(|_| ())?;
     // Insert it into database
     let token_id = authorized_user.token_id;
+    let token_user_id = authorized_user.id;
+    let min_retirement_date = Utc::now() - Duration::seconds(*refresh_token_lifetime);
     match connection.run(move |c| {
-        diesel::update(tokens)
-            .filter(tokens::id.eq(&token_id))
-            .set((access_token.eq(&access_token_uuid), refresh_token.eq(&refresh_token_uuid), tokens::modified.eq(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())))
-            .execute(c)
+        c.transaction(|c| {
+            // Only the refresh token that was presented can be replaced, if other request
+            // replaced it first nothing is updated
+            let updated_rows = diesel::update(tokens)
+                .filter(tokens::id.eq(&token_id))
+                .filter(refresh_token.eq(&presented_refresh_token))
+                .set((access_token.eq(&access_token_uuid), refresh_token.eq(&refresh_token_uuid), tokens::modified.eq(current_timestamp())))
+                .execute(c)?;
+            if updated_rows == 0 {
+                return Ok(0);
+            }
+            // Keep the refresh token being replaced to detect if someone tries to use it again
+            diesel::insert_into(retired_tokens::table)
+                .values((retired_tokens::token_id.eq(&token_id), retired_tokens::user_id.eq(&token_user_id), retired_tokens::refresh_token.eq(&presented_refresh_token)))
+                .execute(c)?;
+            // The retired tokens older than the refresh token lifetime have expired, so they
+            // cannot be replayed anymore
+            diesel::delete(retired_tokens::table)
+                .filter(retired_tokens::user_id.eq(&token_user_id))
+                .filter(retired_tokens::created.lt(min_retirement_date.naive_utc()))
+                .execute(c)?;
+            diesel::QueryResult::Ok(updated_rows)
+        })
     }).await {
         Ok(rows) => {
             match rows {
-                0 => Err(()),
+                0 => Ok(None),
                 // Return the new JWT tokens
-                _ => Ok((access_token_jwt, refresh_token_jwt))
+                _ => Ok(Some((access_token_jwt, refresh_token_jwt)))
             }
         },
         Err(_) => Err(())
     }
 }
 
+async fn check_refresh_reuse(connection: &RockpassDatabase, jwt_keys: &JwtKeys, payload_refresh_token: &str) -> Option<(i32, String)> {
+    // Get UUID value from refresh token and seek for it in the retired ones
+    let uuid = get_uuid_from_token(payload_refresh_token).ok()?;
+    let retired_token = connection.run(move |c| {
+        retired_tokens::table
+            .filter(retired_tokens::refresh_token.eq(&uuid))
+            .select(RetiredToken::as_select())
+            .first(c)
+    }).await.ok()?;
+    // Only a refresh token signed by us is considered a reuse
+    let retired_token_user_id = retired_token.user_id;
+    let user = connection.run(move |c| {
+        users::table
+            .find(&retired_token_user_id)
+            .first::<User>(c)
+    }).await.ok()?;
+    check_jwt(jwt_keys, user.id, user.generation, payload_refresh_token).ok()?;
+    // Revoke the whole token family, the session created after the retirement of the token
+    // (token identifiers can be reused) is not part of it
+    let family_id = retired_token.token_id;
+    let revoked_rows = connection.run(move |c| {
+        c.transaction(|c| {
+            let revoked_rows = diesel::delete(tokens::table)
+                .filter(tokens::id.eq(&family_id))
+                .filter(tokens::user_id.eq(&retired_token.user_id))
+                .filter(tokens::created.le(&retired_token.created))
+                .execute(c)?;
+            diesel::delete(retired_tokens::table)
+                .filter(retired_tokens::token_id.eq(&family_id))
+                .execute(c)?;
+            diesel::QueryResult::Ok(revoked_rows)
+        })
+    }).await.ok()?;
+    match revoked_rows {
+        0 => None,
+        _ => Some((family_id, user.email))
+    }
+}
+
+async fn check_second_factor(connection: &RockpassDatabase, hasher: &Hasher, user: &User, otp: Option<String>) -> Result<(), LoginError> {
+    // Seek for a confirmed TOTP secret, if there is none the user does not use two-factor
+    let totp_user_id = user.id;
+    let totp_secret = connection.run(move |c| {
+        totp_secrets::table
+            .find(&totp_user_id)
+            .filter(totp_secrets::confirmed.eq(true))
+            .select(TotpSecret::as_select())
+            .first(c)
+            .optional()
+    }).await.map_err(|_| LoginError::Unavailable)?;
+    let Some(totp_secret) = totp_secret else {
+        return Ok(());
+    };
+    let Some(otp) = otp else {
+        return Err(LoginError::MissingCode);
+    };
+    if totp::is_code(&otp) {
+        // Check the one-time code and store its step to avoid that it can be used again, only
+        // one of several concurrent logins with the same code can store it
+        let Some(step) = totp::verify(&totp_secret.secret, &otp, Utc::now().timestamp(), totp_secret.last_used_step) else {
+            return Err(LoginError::InvalidCode);
+        };
+        let updated_rows = connection.run(move |c| {
+            diesel::update(totp_secrets::table)
+                .filter(totp_secrets::user_id.eq(&totp_user_id))
+                .filter(totp_secrets::last_used_step.is_null().or(totp_secrets::last_used_step.lt(step)))
+                .set((totp_secrets::last_used_step.eq(step), totp_secrets::modified.eq(current_timestamp())))
+                .execute(c)
+        }).await.map_err(|_| LoginError::Unavailable)?;
+        match updated_rows {
+            1 => Ok(()),
+            _ => Err(LoginError::InvalidCode)
+        }
+    } else {
+        // Check the recovery codes and burn the one that matches
+        let codes: Vec<RecoveryCode> = connection.run(move |c| {
+            recovery_codes::table
+                .filter(recovery_codes::user_id.eq(&totp_user_id))
+                .select(RecoveryCode::as_select())
+                .load(c)
+        }).await.map_err(|_| LoginError::Unavailable)?;
+        let Some(recovery_code_id) = codes.iter()
+            .find(|recovery_code| hasher.verify(otp.trim(), &recovery_code.code))
+            .map(|recovery_code| recovery_code.id) else {
+            return Err(LoginError::InvalidCode);
+        };
+        // The code is only valid if this login is the one that burns it
+        let deleted_rows = connection.run(move |c| {
+            diesel::delete(recovery_codes::table)
+                .filter(recovery_codes::id.eq(&recovery_code_id))
+                .execute(c)
+        }).await.map_err(|_| LoginError::Unavailable)?;
+        match deleted_rows {
+            1 => Ok(()),
+            _ => Err(LoginError::InvalidCode)
+        }
+    }
+}
+
 #[options("/auth/users")]
 pub async fn options_auth_users() -> Status {
     Status::NoContent
 }
 
 #[post("/auth/users", data = "<user>")]
-pub async fn post_auth_users(connection: RockpassDatabase, config: &State<RockpassConfig>, user: Json<NewUser>) -> status::Custom<Json<Value>> {
-    if config.registration_enabled {
-        // Register new user
-        let new_user_email = user.0.email.clone();
-        let bcrypted_password = hash(&user.0.password, BCRYPT_COST).unwrap();
-        let inserted_rows = connection.run(move |c| {
-            diesel::insert_into(users)
-                .values((email.eq(&new_user_email), password.eq(bcrypted_password)))
-                .execute(c)
-        }).await.unwrap_or_default();
-        match inserted_rows {
-            0 => status::Custom(Status::Conflict, Json(json!({"detail": "User already exists"}))),
-            _ => status::Custom(Status::Created, Json(json!({"detail": format!("Created {} user", user.0.email)})))
-        }
-    } else {
-        status::Custom(Status::Forbidden, Json(json!({"detail": "Registration is disabled"})))
+pub async fn post_auth_users(connection: RockpassDatabase, config: &State<RockpassConfig>, hasher: &State<Hasher>, user: JsonBody<NewUser>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // An invitation allows to register even if registration is disabled
+    if ! config.registration_enabled && user.0.invite.is_none() {
+        return Err(RockpassError::Forbidden("Registration is disabled".into()));
+    }
+    // Register new user
+    let new_user_email = user.0.email.clone();
+    let invitation_code_hash = user.0.invite.as_deref().map(invites::hash_code);
+    let Ok(hashed_password) = hasher.hash(&user.0.password) else {
+        return Err(RockpassError::Internal("There was a problem creating the user".into()));
+    };
+    let registration = connection.run(move |c| {
+        c.transaction(|c| {
+            // Use the invitation, it is only spent if the user is really created
+            if let Some(invitation_code_hash) = invitation_code_hash {
+                let used_invitations = diesel::update(invitations::table)
+                    .filter(invitations::code_hash.eq(&invitation_code_hash))
+                    .filter(invitations::uses.lt(invitations::max_uses))
+                    .filter(invitations::expires.gt(Utc::now().naive_utc()))
+                    .set(invitations::uses.eq(invitations::uses + 1))
+                    .execute(c)?;
+                if used_invitations == 0 {
+                    return Ok(None);
+                }
+            }
+            match diesel::insert_or_ignore_into(users)
+                .values((email.eq(&new_user_email), password.eq(hashed_password)))
+                .execute(c)? {
+                0 => Err(diesel::result::Error::RollbackTransaction),
+                inserted_rows => Ok(Some(inserted_rows))
+            }
+        })
+    }).await;
+    match (registration, config.registration_enumeration_safe) {
+        (Ok(None), _) => Err(RockpassError::Forbidden("Invitation is not valid".into())),
+        (Err(diesel::result::Error::RollbackTransaction), false) => Err(RockpassError::Conflict("User already exists".into())),
+        (Err(diesel::result::Error::RollbackTransaction) | Ok(Some(_)), true) =>
+            // Do not reveal if the user already exists
+            Ok(status::Custom(Status::Created, Json(json!({"detail": format!("Registration of {} user accepted", user.0.email)})))),
+        (Ok(Some(_)), false) => Ok(status::Custom(Status::Created, Json(json!({"detail": format!("Created {} user", user.0.email)})))),
+        (Err(_), _) => Err(RockpassError::Internal("There was a problem creating the user".into()))
     }
 }
 
@@ -241,31 +562,67 @@ pub async fn options_auth_users_me() -> Status {
 }
 
 #[get("/auth/users/me")]
-pub async fn get_auth_users_me(authorization: Authorization) -> status::Custom<Json<Value>> {
-    status::Custom(Status::Ok, Json(
+pub async fn get_auth_users_me(authorization: Authorization) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    Ok(status::Custom(Status::Ok, Json(
             json!({
                 "id": authorization.1.id,
                 "email": authorization.1.email
             })
-        ))
+        )))
+}
+
+pub fn delete_user(c: &mut SqliteConnection, deleted_user_id: i32) -> QueryResult<usize> {
+    // Delete the user with all its data, foreign keys are not enforced so nothing is deleted in cascade
+    c.transaction(|c| {
+        diesel::delete(passwords::table)
+            .filter(passwords::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::delete(deleted_passwords::table)
+            .filter(deleted_passwords::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::delete(retired_tokens::table)
+            .filter(retired_tokens::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::delete(tokens::table)
+            .filter(tokens::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::delete(totp_secrets::table)
+            .filter(totp_secrets::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::delete(recovery_codes::table)
+            .filter(recovery_codes::user_id.eq(&deleted_user_id))
+            .execute(c)?;
+        diesel::update(invitations::table)
+            .filter(invitations::created_by.eq(&deleted_user_id))
+            .set(invitations::created_by.eq(None::<i32>))
+            .execute(c)?;
+        diesel::delete(users)
+            .filter(users::id.eq(&deleted_user_id))
+            .execute(c)
+    })
+}
+
+pub fn delete_retired_tokens(c: &mut SqliteConnection, owner_id: i32) -> QueryResult<usize> {
+    // Forget the retired refresh tokens of the sessions that do not exist anymore, they are only
+    // kept to revoke their session if they are used again
+    diesel::delete(retired_tokens::table)
+        .filter(retired_tokens::user_id.eq(&owner_id))
+        .filter(diesel::dsl::not(retired_tokens::token_id.eq_any(tokens::table.select(tokens::id))))
+        .execute(c)
 }
 
 #[delete("/auth/users/me", data = "<user_password>")]
-pub async fn delete_auth_users_me(authorization: Authorization, user_password: Json<UserPassword>) -> status::Custom<Json<Value>> {
-    if verify(&user_password.0.current_password, &authorization.1.password).unwrap() {
+pub async fn delete_auth_users_me(authorization: Authorization, hasher: &State<Hasher>, user_password: JsonBody<UserPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    if hasher.verify(&user_password.0.current_password, &authorization.1.password) {
         let connection = authorization.0;
         let authorized_user_id = authorization.1.id;
-        // Delete current user
-        match connection.run(move |c| {
-            diesel::delete(users)
-                .filter(users::id.eq(&authorized_user_id))
-                .execute(c)
-            }).await {
-                Ok(_) => status::Custom(Status::Ok, Json(json!({"detail": "Your user has been deleted"}))),
-                Err(_) => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem deleting your user"})))
+        // Delete current user with all its data
+        match connection.run(move |c| delete_user(c, authorized_user_id)).await {
+            Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": "Your user has been deleted"})))),
+            Err(e) => Err(RockpassError::internal(e, "There was a problem deleting your user"))
         }
     } else {
-        status::Custom(Status::Forbidden, Json(json!({"detail": "Password does not match with the one stored in database"})))
+        Err(RockpassError::Forbidden("Password does not match with the one stored in database".into()))
     }
 }
 
@@ -275,32 +632,157 @@ pub async fn options_auth_users_set_password() -> Status {
 }
 
 #[post("/auth/users/set_password", data = "<new_user_password>")]
-pub async fn post_auth_users_set_password(authorization: Authorization, new_user_password: Json<NewUserPassword>) -> status::Custom<Json<Value>> {
-    if verify(&new_user_password.0.current_password, &authorization.1.password).unwrap() {
+pub async fn post_auth_users_set_password(authorization: Authorization, hasher: &State<Hasher>, new_user_password: JsonBody<NewUserPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    if hasher.verify(&new_user_password.0.current_password, &authorization.1.password) {
         let connection = authorization.0;
         let authorized_user_id = authorization.1.id;
-        let bcrypted_password = hash(&new_user_password.0.new_password, BCRYPT_COST).unwrap();
+        let Ok(hashed_password) = hasher.hash(&new_user_password.0.new_password) else {
+            return Err(RockpassError::Internal("There was a problem updating the password".into()));
+        };
         // Change user password
         let updated_rows = connection.run(move |c| {
             diesel::update(users)
                 .filter(users::id.eq(&authorized_user_id))
-                .set(password.eq(bcrypted_password))
+                .set((password.eq(hashed_password), generation.eq(generation + 1)))
                 .execute(c)
-        }).await.unwrap_or_default();
+        }).await.map_err(|e| RockpassError::internal(e, "There was a problem updating the password"))?;
         match updated_rows {
-            0 => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem updating the password"}))),
+            0 => Err(RockpassError::Internal("There was a problem updating the password".into())),
             _ => {
                 // Delete all user tokens after password change
                 let deleted_rows = connection.run(move |c| {
-                    diesel::delete(tokens)
-                        .filter(tokens::user_id.eq(&authorized_user_id))
-                        .execute(c)
-                }).await.unwrap_or_default();
-                status::Custom(Status::Ok, Json(json!({"detail": format!("Password changed for user {} and deleted {} old tokens", authorization.1.email, deleted_rows)})))
+                    c.transaction(|c| {
+                        let deleted_rows = diesel::delete(tokens)
+                            .filter(tokens::user_id.eq(&authorized_user_id))
+                            .execute(c)?;
+                        delete_retired_tokens(c, authorized_user_id)?;
+                        diesel::QueryResult::Ok(deleted_rows)
+                    })
+                }).await.map_err(|e| RockpassError::internal(e, "There was a problem deleting the old tokens"))?;
+                Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Password changed for user {} and deleted {} old tokens", authorization.1.email, deleted_rows)}))))
             }
         }
     } else {
-        status::Custom(Status::Forbidden, Json(json!({"detail": "Old password does not match with the one stored in database"})))
+        Err(RockpassError::Forbidden("Old password does not match with the one stored in database".into()))
+    }
+}
+
+#[options("/auth/users/me/totp")]
+pub async fn options_auth_users_me_totp() -> Status {
+    Status::NoContent
+}
+
+#[post("/auth/users/me/totp")]
+pub async fn post_auth_users_me_totp(authorization: Authorization) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    let authorized_user_id = authorization.1.id;
+    // Generate a new secret, a previous not confirmed enrolment is replaced
+    let secret = totp::new_secret();
+    let new_secret = secret.clone();
+    match connection.run(move |c| {
+        let confirmed = totp_secrets::table
+            .find(&authorized_user_id)
+            .select(totp_secrets::confirmed)
+            .first::<bool>(c)
+            .optional()?;
+        if confirmed == Some(true) {
+            return Ok(0);
+        }
+        diesel::replace_into(totp_secrets::table)
+            .values((totp_secrets::user_id.eq(&authorized_user_id), totp_secrets::secret.eq(&new_secret)))
+            .execute(c)
+    }).await {
+        Ok(0) => Err(RockpassError::Conflict("Two-factor authentication is already enabled".into())),
+        Ok(_) => Ok(status::Custom(Status::Created, Json(
+                json!({
+                    "secret": secret,
+                    "uri": totp::provisioning_uri(&secret, &authorization.1.email)
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem enrolling two-factor authentication"))
+    }
+}
+
+#[delete("/auth/users/me/totp", data = "<user_password>")]
+pub async fn delete_auth_users_me_totp(authorization: Authorization, hasher: &State<Hasher>, user_password: JsonBody<UserPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    if hasher.verify(&user_password.0.current_password, &authorization.1.password) {
+        let connection = authorization.0;
+        let authorized_user_id = authorization.1.id;
+        // Delete the TOTP secret and the recovery codes
+        match connection.run(move |c| {
+            diesel::delete(recovery_codes::table)
+                .filter(recovery_codes::user_id.eq(&authorized_user_id))
+                .execute(c)?;
+            diesel::delete(totp_secrets::table)
+                .filter(totp_secrets::user_id.eq(&authorized_user_id))
+                .execute(c)
+        }).await {
+            Ok(0) => Err(RockpassError::NotFound("Two-factor authentication is not enabled".into())),
+            Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": "Two-factor authentication disabled"})))),
+            Err(e) => Err(RockpassError::internal(e, "There was a problem disabling two-factor authentication"))
+        }
+    } else {
+        Err(RockpassError::Forbidden("Password does not match with the one stored in database".into()))
+    }
+}
+
+#[options("/auth/users/me/totp/confirm")]
+pub async fn options_auth_users_me_totp_confirm() -> Status {
+    Status::NoContent
+}
+
+#[post("/auth/users/me/totp/confirm", data = "<totp_code>")]
+pub async fn post_auth_users_me_totp_confirm(authorization: Authorization, hasher: &State<Hasher>, totp_code: JsonBody<TotpCode>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    let authorized_user_id = authorization.1.id;
+    // Seek for the pending enrolment
+    let totp_secret = match connection.run(move |c| {
+        totp_secrets::table
+            .find(&authorized_user_id)
+            .select(TotpSecret::as_select())
+            .first(c)
+            .optional()
+    }).await {
+        Ok(Some(totp_secret)) => totp_secret,
+        Ok(None) => return Err(RockpassError::NotFound("There is no pending two-factor authentication enrolment".into())),
+        Err(e) => return Err(RockpassError::internal(e, "There was a problem confirming two-factor authentication"))
+    };
+    if totp_secret.confirmed {
+        return Err(RockpassError::Conflict("Two-factor authentication is already enabled".into()));
+    }
+    let Some(step) = totp::verify(&totp_secret.secret, &totp_code.0.code, Utc::now().timestamp(), None) else {
+        return Err(RockpassError::Forbidden("Invalid two-factor authentication code".into()));
+    };
+    // Generate the recovery codes, only their hashes are stored
+    let new_recovery_codes = totp::new_recovery_codes();
+    let Ok(hashed_recovery_codes) = new_recovery_codes.iter()
+        .map(|recovery_code| hasher.hash(recovery_code))
+        .collect::<Result<Vec<String>, ()>>() else {
+        return Err(RockpassError::Internal("There was a problem confirming two-factor authentication".into()));
+    };
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            diesel::update(totp_secrets::table)
+                .filter(totp_secrets::user_id.eq(&authorized_user_id))
+                .set((totp_secrets::confirmed.eq(true), totp_secrets::last_used_step.eq(step), totp_secrets::modified.eq(current_timestamp())))
+                .execute(c)?;
+            diesel::delete(recovery_codes::table)
+                .filter(recovery_codes::user_id.eq(&authorized_user_id))
+                .execute(c)?;
+            diesel::insert_into(recovery_codes::table)
+                .values(hashed_recovery_codes.iter()
+                    .map(|hashed_recovery_code| (recovery_codes::user_id.eq(authorized_user_id), recovery_codes::code.eq(hashed_recovery_code)))
+                    .collect::<Vec<_>>())
+                .execute(c)
+        })
+    }).await {
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(
+                json!({
+                    "detail": "Two-factor authentication enabled",
+                    "recovery_codes": new_recovery_codes
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem confirming two-factor authentication"))
     }
 }
 
@@ -310,7 +792,15 @@ pub async fn options_auth_jwt_create() -> Status {
 }
 
 #[post("/auth/jwt/create", data = "<user>")]
-pub async fn post_auth_jwt_create(connection: RockpassDatabase, config: &State<RockpassConfig>, user: Json<NewUser>) -> status::Custom<Json<Value>> {
+pub async fn post_auth_jwt_create(connection: RockpassDatabase, config: &State<RockpassConfig>, jwt_keys: &State<JwtKeys>, hasher: &State<Hasher>, client_info: ClientInfo, user: JsonBody<UserLogin>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // Refuse to check anything while the account or the client IP are locked
+    let login_subjects = lockout::subjects(&user.0.email, client_info.ip.as_deref());
+    let locked_subjects = login_subjects.clone();
+    match connection.run(move |c| lockout::retry_after(c, &locked_subjects)).await {
+        Ok(Some(seconds)) => return Err(RockpassError::TooManyRequests(format!("Too many failed login attempts, try again in {} seconds", seconds), seconds)),
+        Ok(None) => (),
+        Err(e) => return Err(RockpassError::internal(e, "There was a problem checking the failed login attempts"))
+    }
     // Seek for user in database
     let user_email = user.0.email;
     let results: Vec<User> = connection.run(move |c| {
@@ -318,26 +808,74 @@ pub async fn post_auth_jwt_create(connection: RockpassDatabase, config: &State<R
             .filter(email.eq(&user_email))
             .limit(1)
             .load::<User>(c)
-    }).await.expect("load user");
-    // If user found verify password
-    if (results.is_empty()) || (! verify(&user.0.password, &results[0].password).unwrap()) {
-        return status::Custom(Status::Unauthorized, Json(json!({"detail": "No active account found with the given credentials"})));
+    }).await.map_err(|e| RockpassError::internal(e, "There was a problem checking the credentials"))?;
+    // If user found verify password and, if it has two-factor authentication enabled, the one-time code
+    let password_matches = match results.first() {
+        Some(found_user) => hasher.verify(&user.0.password, &found_user.password),
+        None => {
+            hasher.verify_dummy(&user.0.password);
+            false
+        }
+    };
+    let authentication = match password_matches {
+        true => check_second_factor(&connection, hasher, &results[0], user.0.otp).await,
+        false => Err(LoginError::Credentials)
+    };
+    match authentication {
+        // Asking for the code is the first step of a two-factor login, it is not a failure
+        Err(LoginError::MissingCode) => return Err(RockpassError::Unauthorized(LoginError::MissingCode.detail().into())),
+        Err(LoginError::Unavailable) => return Err(RockpassError::Internal(LoginError::Unavailable.detail().into())),
+        _ => ()
+    }
+    if let Err(error) = authentication {
+        let failed_subjects = login_subjects;
+        let backoff = lockout::Backoff::from_config(config);
+        if connection.run(move |c| lockout::record_failure(c, &backoff, &failed_subjects)).await.is_err() {
+            warn!("Cannot record the failed login attempt of user {}", results.first().map_or("unknown", |user| user.email.as_str()));
+        }
+        return Err(RockpassError::Unauthorized(error.detail().into()));
+    }
+    // Disabled users cannot login even with the right credentials
+    if results[0].disabled {
+        return Err(RockpassError::Forbidden("Your user has been disabled by an administrator".into()));
+    }
+    // Forget the previous failures of the account
+    let (account_scope, account_subject) = login_subjects[0].clone();
+    if connection.run(move |c| lockout::reset(c, account_scope, &account_subject)).await.is_err() {
+        warn!("Cannot reset the failed login attempts of user {}", results[0].email);
+    }
+    // Upgrade the stored hash if it was made with other algorithm or cost than the configured ones
+    if hasher.needs_rehash(&results[0].password) && let Ok(hashed_password) = hasher.hash(&user.0.password) {
+        let rehash_user_id = results[0].id;
+        let old_hashed_password = results[0].password.clone();
+        if connection.run(move |c| {
+            diesel::update(users)
+                .filter(users::id.eq(&rehash_user_id))
+                .filter(password.eq(&old_hashed_password))
+                .set(password.eq(hashed_password))
+                .execute(c)
+        }).await.is_err() {
+            warn!("Cannot update the password hash of user {}", results[0].email);
+        }
     }
     // Generate new token
-    match create_tokens(&connection, &results[0], &config.access_token_lifetime, &config.refresh_token_lifetime).await {
+    match create_tokens(&connection, jwt_keys, &results[0], client_info, &config.access_token_lifetime, &config.refresh_token_lifetime).await {
         Ok(created_token) => {
-            // Delete expired tokens after login
+            // Delete expired tokens after login and the retired ones of deleted sessions
             let min_modification_date = Utc::now() - Duration::seconds(config.refresh_token_lifetime);
             let token_user_id = results[0].id;
-            connection.run(move |c| {
+            if let Err(e) = connection.run(move |c| {
                 diesel::delete(tokens::table)
                     .filter(tokens::user_id.eq(&token_user_id))
                     .filter(tokens::modified.lt(min_modification_date.format("%Y-%m-%d %H:%M:%S").to_string()))
-                    .execute(c)
-            }).await.expect("delete expired tokens");
-            status::Custom(Status::Created, Json(json!({"access": created_token.0, "refresh": created_token.1})))
+                    .execute(c)?;
+                delete_retired_tokens(c, token_user_id)
+            }).await {
+                warn!("Cannot delete the expired tokens of user {}: {}", results[0].email, e);
+            }
+            Ok(status::Custom(Status::Created, Json(json!({"access": created_token.0, "refresh": created_token.1}))))
         },
-        Err(()) => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem generating the new token"})))
+        Err(()) => Err(RockpassError::Internal("There was a problem generating the new token".into()))
     }
 }
 
@@ -347,65 +885,782 @@ pub async fn options_auth_jwt_refresh() -> Status {
 }
 
 #[post("/auth/jwt/refresh", data = "<jwt_refresh_token>")]
-pub async fn post_auth_jwt_refresh(connection: RockpassDatabase, config: &State<RockpassConfig>, jwt_refresh_token: Json<JWTRefreshToken>) -> status::Custom<Json<Value>> {
-    // Check the refresh token
-    match check_refresh(&connection, &jwt_refresh_token.0.refresh).await {
-        Ok(authorized_user) => {
-            // Generate new token
-            match refresh_tokens(&connection, &authorized_user, &config.access_token_lifetime, &config.refresh_token_lifetime).await {
-                Ok(refreshed_token) => status::Custom(Status::Created, Json(json!({"access": refreshed_token.0, "refresh": refreshed_token.1}))),
-                Err(()) => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem generating the new token"})))
+pub async fn post_auth_jwt_refresh(connection: RockpassDatabase, config: &State<RockpassConfig>, jwt_keys: &State<JwtKeys>, jwt_refresh_token: JsonBody<JWTRefreshToken>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // Check the refresh token and generate new token, the rotation fails if other request
+    // replaced the same refresh token in the meantime
+    let refreshed_token = match check_refresh(&connection, jwt_keys, &jwt_refresh_token.0.refresh).await {
+        Ok(authorized_user) => refresh_tokens(&connection, jwt_keys, &authorized_user, &jwt_refresh_token.0.refresh, &config.access_token_lifetime, &config.refresh_token_lifetime).await
+            .map_err(|_| RockpassError::Internal("There was a problem generating the new token".into()))?,
+        Err(_) => None
+    };
+    match refreshed_token {
+        Some(refreshed_token) => Ok(status::Custom(Status::Created, Json(json!({"access": refreshed_token.0, "refresh": refreshed_token.1})))),
+        None => {
+            // A retired refresh token means that it has been stolen or leaked
+            if let Some((family_id, family_user_email)) = check_refresh_reuse(&connection, jwt_keys, &jwt_refresh_token.0.refresh).await {
+                warn!("Refresh token reuse detected for user {}, session {} has been revoked", family_user_email, family_id);
             }
+            Err(RockpassError::Unauthorized("Your refresh token is not valid".into()))
+        }
+    }
+}
+
+#[options("/auth/jwt/logout")]
+pub async fn options_auth_jwt_logout() -> Status {
+    Status::NoContent
+}
+
+#[post("/auth/jwt/logout?<everywhere>")]
+pub async fn post_auth_jwt_logout(authorization: Authorization, everywhere: Option<bool>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    let authorized_user_id = authorization.1.id;
+    let current_token_id = authorization.1.token_id;
+    let everywhere = everywhere.unwrap_or(false);
+    // Delete current token or all user tokens
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            let deleted_rows = if everywhere {
+                diesel::delete(tokens)
+                    .filter(tokens::user_id.eq(&authorized_user_id))
+                    .execute(c)?
+            } else {
+                diesel::delete(tokens)
+                    .filter(tokens::id.eq(&current_token_id))
+                    .execute(c)?
+            };
+            delete_retired_tokens(c, authorized_user_id)?;
+            diesel::QueryResult::Ok(deleted_rows)
+        })
+    }).await {
+        Ok(deleted_rows) => if everywhere {
+            Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Logged out from {deleted_rows} sessions")}))))
+        } else {
+            Ok(status::Custom(Status::Ok, Json(json!({"detail": "Logged out"}))))
         },
-        Err(_) => status::Custom(Status::Unauthorized, Json(json!({"detail": "Your refresh token is not valid"})))
+        Err(e) => Err(RockpassError::internal(e, "There was a problem logging out"))
+    }
+}
+
+#[options("/auth/sessions")]
+pub async fn options_auth_sessions() -> Status {
+    Status::NoContent
+}
+
+#[get("/auth/sessions")]
+pub async fn get_auth_sessions(authorization: Authorization) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    // Seek for user tokens in database
+    let authorized_user_id = authorization.1.id;
+    match connection.run(move |c| {
+        tokens::table
+            .filter(tokens::user_id.eq(&authorized_user_id))
+            .load::<DBToken>(c)
+    }).await {
+        Ok(results) => Ok(status::Custom(Status::Ok, Json(
+                json!({
+                    "count": results.len(),
+                    "results": results.iter().map(|session| json!({
+                        "id": session.id,
+                        "user_agent": session.user_agent,
+                        "ip": session.ip,
+                        "created": session.created,
+                        "modified": session.modified,
+                        "current": session.id == authorization.1.token_id
+                    })).collect::<Vec<Value>>()
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem getting the sessions"))
+    }
+}
+
+#[delete("/auth/sessions")]
+pub async fn delete_auth_sessions(authorization: Authorization) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    // Delete all user tokens except the current one
+    let authorized_user_id = authorization.1.id;
+    let current_token_id = authorization.1.token_id;
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            let deleted_rows = diesel::delete(tokens)
+                .filter(tokens::user_id.eq(&authorized_user_id))
+                .filter(tokens::id.ne(&current_token_id))
+                .execute(c)?;
+            delete_retired_tokens(c, authorized_user_id)?;
+            diesel::QueryResult::Ok(deleted_rows)
+        })
+    }).await {
+        Ok(deleted_rows) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted {deleted_rows} other sessions")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem deleting the sessions"))
+    }
+}
+
+#[options("/auth/sessions/<_session_id>")]
+pub async fn options_auth_sessions_id(_session_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[delete("/auth/sessions/<session_id>")]
+pub async fn delete_auth_sessions_id(authorization: Authorization, session_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    // Delete the user token with given id
+    let authorized_user_id = authorization.1.id;
+    let deleted_rows = connection.run(move |c| {
+        c.transaction(|c| {
+            let deleted_rows = diesel::delete(tokens)
+                .filter(tokens::id.eq(&session_id))
+                .filter(tokens::user_id.eq(&authorized_user_id))
+                .execute(c)?;
+            delete_retired_tokens(c, authorized_user_id)?;
+            diesel::QueryResult::Ok(deleted_rows)
+        })
+    }).await.map_err(|e| RockpassError::internal(e, "There was a problem deleting the session"))?;
+    match deleted_rows {
+        0 => Err(RockpassError::NotFound(format!("Session {session_id} not found in database"))),
+        _ => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted session with id {session_id}")}))))
+    }
+}
+
+#[options("/admin/invitations")]
+pub async fn options_admin_invitations() -> Status {
+    Status::NoContent
+}
+
+#[get("/admin/invitations")]
+pub async fn get_admin_invitations(admin: Admin) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Seek for invitations in database, the codes cannot be recovered
+    match connection.run(|c| {
+        invitations::table
+            .select(Invitation::as_select())
+            .load(c)
+    }).await {
+        Ok(results) => Ok(status::Custom(Status::Ok, Json(
+                json!({
+                    "count": results.len(),
+                    "results": results
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem getting the invitations"))
+    }
+}
+
+#[post("/admin/invitations", data = "<new_invitation>")]
+pub async fn post_admin_invitations(admin: Admin, config: &State<RockpassConfig>, new_invitation: JsonBody<NewInvitation>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let invitation_lifetime = new_invitation.0.lifetime.unwrap_or(config.invitation_lifetime);
+    if new_invitation.0.max_uses < 1 || invitation_lifetime < 1 {
+        return Err(RockpassError::BadRequest("The invitation must have at least one use and a positive lifetime".into()));
+    }
+    let connection = admin.0;
+    let admin_user_id = admin.1.id;
+    // Only the hash of the code is stored, so it is shown once
+    let invitation_code = invites::new_code();
+    let invitation_code_hash = invites::hash_code(&invitation_code);
+    let invitation_max_uses = new_invitation.0.max_uses;
+    // The dates are compared as text in the database, so they must keep four digit years
+    let Some(invitation_expires) = Duration::try_seconds(invitation_lifetime)
+        .and_then(|lifetime| Utc::now().naive_utc().checked_add_signed(lifetime))
+        .filter(|expires| expires.year() <= 9999) else {
+        return Err(RockpassError::BadRequest("The invitation lifetime is too long".into()));
+    };
+    match connection.run(move |c| {
+        diesel::insert_into(invitations::table)
+            .values((
+                invitations::code_hash.eq(&invitation_code_hash),
+                invitations::max_uses.eq(invitation_max_uses),
+                invitations::expires.eq(invitation_expires),
+                invitations::created_by.eq(admin_user_id)
+            ))
+            .returning(invitations::id)
+            .get_result::<i32>(c)
+    }).await {
+        Ok(invitation_id) => Ok(status::Custom(Status::Created, Json(
+                json!({
+                    "id": invitation_id,
+                    "code": invitation_code,
+                    "max_uses": invitation_max_uses,
+                    "expires": invitation_expires
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem creating the invitation"))
     }
 }
 
+#[options("/admin/invitations/<_invitation_id>")]
+pub async fn options_admin_invitations_id(_invitation_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[delete("/admin/invitations/<invitation_id>")]
+pub async fn delete_admin_invitations_id(admin: Admin, invitation_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Delete the invitation so its code cannot be used anymore
+    let deleted_rows = connection.run(move |c| {
+        diesel::delete(invitations::table.find(invitation_id))
+            .execute(c)
+    }).await.map_err(|e| RockpassError::internal(e, "There was a problem deleting the invitation"))?;
+    match deleted_rows {
+        0 => Err(RockpassError::NotFound(format!("Invitation {invitation_id} not found in database"))),
+        _ => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted invitation with id {invitation_id}")}))))
+    }
+}
+
+#[options("/admin/backup")]
+pub async fn options_admin_backup() -> Status {
+    Status::NoContent
+}
+
+#[get("/admin/backup")]
+pub async fn get_admin_backup(admin: Admin) -> Result<Backup, RockpassError> {
+    let connection = admin.0;
+    // Write a consistent copy of the database to a private temporary directory and stream it,
+    // the file is unlinked once open so it does not stay in disk. All the file work is done
+    // with the connection, out of the async workers
+    let backup_file = connection.run(|c| {
+        let backup_directory = backup::private_directory()?;
+        let backup_path = backup_directory.join("rockpass.sqlite");
+        let written = backup::backup(c, &backup_path.to_string_lossy())
+            .and_then(|_| std::fs::File::open(&backup_path).map_err(|e| e.to_string()));
+        let _ = std::fs::remove_file(&backup_path);
+        let _ = std::fs::remove_dir(&backup_directory);
+        written
+    }).await.map_err(|e| RockpassError::internal(e, "Cannot write the backup"))?;
+    let filename = format!("rockpass-{}.sqlite", Utc::now().format("%Y%m%d%H%M%S"));
+    Ok(Backup(File::from_std(backup_file), Header::new("Content-Disposition", format!("attachment; filename=\"{filename}\""))))
+}
+
+#[options("/admin/users")]
+pub async fn options_admin_users() -> Status {
+    Status::NoContent
+}
+
+#[get("/admin/users")]
+pub async fn get_admin_users(admin: Admin) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Seek for all users in database
+    match connection.run(|c| {
+        users::table
+            .order(users::id)
+            .select(UserSummary::as_select())
+            .load(c)
+    }).await {
+        Ok(results) => Ok(status::Custom(Status::Ok, Json(
+                json!({
+                    "count": results.len(),
+                    "results": results
+                })
+            ))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem getting the users"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>")]
+pub async fn options_admin_users_id(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[delete("/admin/users/<deleted_user_id>")]
+pub async fn delete_admin_users_id(admin: Admin, deleted_user_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    if deleted_user_id == admin.1.id {
+        return Err(RockpassError::Conflict("You cannot delete your own user from the administration".into()));
+    }
+    let connection = admin.0;
+    // Delete the user with all its data
+    match connection.run(move |c| delete_user(c, deleted_user_id)).await {
+        Ok(0) => Err(RockpassError::NotFound(format!("User {deleted_user_id} not found in database"))),
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted user with id {deleted_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem deleting the user"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>/disable")]
+pub async fn options_admin_users_id_disable(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/admin/users/<disabled_user_id>/disable")]
+pub async fn post_admin_users_id_disable(admin: Admin, disabled_user_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    if disabled_user_id == admin.1.id {
+        return Err(RockpassError::Conflict("You cannot disable your own user".into()));
+    }
+    let connection = admin.0;
+    // Disable the user and close all its sessions
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            diesel::delete(tokens)
+                .filter(tokens::user_id.eq(&disabled_user_id))
+                .execute(c)?;
+            delete_retired_tokens(c, disabled_user_id)?;
+            diesel::update(users)
+                .filter(users::id.eq(&disabled_user_id))
+                .set(disabled.eq(true))
+                .execute(c)
+        })
+    }).await {
+        Ok(0) => Err(RockpassError::NotFound(format!("User {disabled_user_id} not found in database"))),
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Disabled user with id {disabled_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem disabling the user"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>/enable")]
+pub async fn options_admin_users_id_enable(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/admin/users/<enabled_user_id>/enable")]
+pub async fn post_admin_users_id_enable(admin: Admin, enabled_user_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Allow the user to login again
+    match connection.run(move |c| {
+        diesel::update(users)
+            .filter(users::id.eq(&enabled_user_id))
+            .set(disabled.eq(false))
+            .execute(c)
+    }).await {
+        Ok(0) => Err(RockpassError::NotFound(format!("User {enabled_user_id} not found in database"))),
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Enabled user with id {enabled_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem enabling the user"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>/set_password")]
+pub async fn options_admin_users_id_set_password(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/admin/users/<reset_user_id>/set_password", data = "<reset_user_password>")]
+pub async fn post_admin_users_id_set_password(admin: Admin, hasher: &State<Hasher>, reset_user_id: i32, reset_user_password: JsonBody<ResetUserPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    let Ok(hashed_password) = hasher.hash(&reset_user_password.0.new_password) else {
+        return Err(RockpassError::Internal("There was a problem updating the password".into()));
+    };
+    // Change the user password and close all its sessions
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            diesel::delete(tokens)
+                .filter(tokens::user_id.eq(&reset_user_id))
+                .execute(c)?;
+            delete_retired_tokens(c, reset_user_id)?;
+            diesel::update(users)
+                .filter(users::id.eq(&reset_user_id))
+                .set((password.eq(hashed_password), generation.eq(generation + 1)))
+                .execute(c)
+        })
+    }).await {
+        Ok(0) => Err(RockpassError::NotFound(format!("User {reset_user_id} not found in database"))),
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Password changed for user with id {reset_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem updating the password"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>/sessions")]
+pub async fn options_admin_users_id_sessions(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[delete("/admin/users/<revoked_user_id>/sessions")]
+pub async fn delete_admin_users_id_sessions(admin: Admin, revoked_user_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Close all the sessions of the user
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            diesel::delete(retired_tokens::table)
+                .filter(retired_tokens::user_id.eq(&revoked_user_id))
+                .execute(c)?;
+            diesel::delete(tokens)
+                .filter(tokens::user_id.eq(&revoked_user_id))
+                .execute(c)
+        })
+    }).await {
+        Ok(deleted_rows) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted {deleted_rows} sessions of user with id {revoked_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem deleting the sessions"))
+    }
+}
+
+#[options("/admin/users/<_managed_user_id>/unlock")]
+pub async fn options_admin_users_id_unlock(_managed_user_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/admin/users/<unlocked_user_id>/unlock")]
+pub async fn post_admin_users_id_unlock(admin: Admin, unlocked_user_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = admin.0;
+    // Forget the failed logins of the user so it can login again
+    match connection.run(move |c| {
+        let unlocked_user_email = users::table
+            .find(&unlocked_user_id)
+            .select(email)
+            .first::<String>(c)
+            .optional()?;
+        match unlocked_user_email {
+            Some(unlocked_user_email) => {
+                let (scope, subject) = &lockout::subjects(&unlocked_user_email, None)[0];
+                lockout::reset(c, scope, subject).map(Some)
+            },
+            None => Ok(None)
+        }
+    }).await {
+        Ok(None) => Err(RockpassError::NotFound(format!("User {unlocked_user_id} not found in database"))),
+        Ok(Some(_)) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Unlocked user with id {unlocked_user_id}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem unlocking the user"))
+    }
+}
+
+#[options("/admin/lockouts/ip/<_locked_ip>")]
+pub async fn options_admin_lockouts_ip(_locked_ip: &str) -> Status {
+    Status::NoContent
+}
+
+#[delete("/admin/lockouts/ip/<locked_ip>")]
+pub async fn delete_admin_lockouts_ip(admin: Admin, locked_ip: &str) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // The address is stored as the client IP is written, so it is parsed to find it
+    let Ok(locked_ip) = locked_ip.parse::<IpAddr>() else {
+        return Err(RockpassError::BadRequest(format!("{locked_ip} is not a valid IP address")));
+    };
+    let connection = admin.0;
+    // Forget the failed logins from the IP so it can login again
+    match connection.run(move |c| lockout::reset(c, lockout::IP, &locked_ip.to_string())).await {
+        Ok(_) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Unlocked IP {locked_ip}")})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem unlocking the IP"))
+    }
+}
+
+#[options("/.well-known/jwks.json")]
+pub async fn options_well_known_jwks() -> Status {
+    Status::NoContent
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn get_well_known_jwks(jwt_keys: &State<JwtKeys>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // Publish public keys so other services can verify access tokens
+    Ok(status::Custom(Status::Ok, Json(json!({"keys": jwt_keys.jwks()}))))
+}
+
 #[options("/passwords")]
 pub async fn options_passwords() -> Status {
     Status::NoContent
 }
 
-#[get("/passwords?<search>")]
-pub async fn get_passwords(authorization: Authorization, search: Option<String>) -> status::Custom<Json<Value>> {
+fn filter_passwords(authorized_user_id: i32, filters: &PasswordsQuery, site_lookup: Option<&(String, Option<String>)>) -> passwords::BoxedQuery<'static, Sqlite> {
+    // Build the query of the passwords of the user that match the filters
+    let mut query = passwords::table
+        .filter(passwords::user_id.eq(authorized_user_id))
+        .into_boxed();
+    if let Some(search) = &filters.search {
+        query = query.filter(passwords::site.like(format!("%{search}%")));
+    }
+    if let Some(login_filter) = &filters.login {
+        query = query.filter(passwords::login.eq(login_filter.clone()));
+    }
+    if let Some(site_filter) = &filters.site {
+        query = query.filter(passwords::site.eq(site_filter.clone()));
+    }
+    if let Some((canonical_site, site_domain)) = site_lookup {
+        // Passwords of the registrable domain of the site and of all its subdomains, or only of
+        // the same site if it does not have one
+        query = match site_domain {
+            Some(site_domain) => {
+                let escaped_site_domain = site_domain.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+                query.filter(passwords::site_canonical.eq(site_domain.clone())
+                    .or(passwords::site_canonical.like(format!("%.{escaped_site_domain}")).escape('\\')))
+            },
+            None => query.filter(passwords::site_canonical.eq(canonical_site.clone()))
+        };
+    }
+    query
+}
+
+fn page_uri(uri: &Origin<'_>, page_offset: i64) -> String {
+    // Same request with other offset, the first page is linked without it
+    let mut segments = uri.query()
+        .map(|query| query.raw_segments().filter(|segment| ! segment.as_str().starts_with("offset=")).map(|segment| segment.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    if page_offset > 0 {
+        segments.push(format!("offset={page_offset}"));
+    }
+    match segments.is_empty() {
+        true => uri.path().to_string(),
+        false => format!("{}?{}", uri.path(), segments.join("&"))
+    }
+}
+
+#[get("/passwords?<filters..>")]
+pub async fn get_passwords(authorization: Authorization, sites: &State<Sites>, accept: Option<&Accept>, uri: &Origin<'_>, filters: PasswordsQuery) -> Result<Listing, RockpassError> {
     let connection = authorization.0;
-    // Seek for passwords in database
-    let authorized_user_id = authorization.1.id;
-    let results: Vec<Password> = match search {
-        Some(search) => connection.run(move |c| {
-            passwords::table
-                .filter(passwords::user_id.eq(&authorized_user_id))
-                .filter(passwords::site.like(&format!("%{search}%")))
-                .load::<Password>(c)
-        }).await.expect("load passwords"),
-        None => connection.run(move |c| {
-            passwords::table
-                .filter(passwords::user_id.eq(&authorized_user_id))
-                .load::<Password>(c)
-        }).await.expect("load passwords")
+    // The passwords can be listed as CSV asking for it in the Accept header or in the query
+    let csv = match filters.format.as_deref() {
+        Some("csv") => true,
+        Some("json") => false,
+        Some(unknown_format) => return Err(RockpassError::BadRequest(format!("Format {unknown_format} is not supported, use json or csv"))),
+        None => accept.is_some_and(|accept| accept.preferred().is_csv())
     };
-    status::Custom(Status::Ok, Json(
+    if ! matches!(filters.ordering.as_deref(), None | Some("site" | "-site" | "created" | "-created" | "modified" | "-modified")) {
+        return Err(RockpassError::BadRequest("Ordering must be one of site, created or modified, prefixed by - to reverse it".into()));
+    }
+    if filters.limit.is_some_and(|page_limit| ! (1..=MAX_PAGE_LIMIT).contains(&page_limit)) || filters.offset.is_some_and(|page_offset| page_offset < 0) {
+        return Err(RockpassError::BadRequest(format!("Limit must be between 1 and {MAX_PAGE_LIMIT} and offset cannot be negative")));
+    }
+    // Seek for passwords in database, the total count is only needed for a page
+    let authorized_user_id = authorization.1.id;
+    let paginated = filters.limit.is_some() || filters.offset.is_some();
+    let page_limit = filters.limit;
+    let page_offset = filters.offset.unwrap_or(0);
+    // The domain lookup can be any URL of the site
+    let site_lookup = filters.domain.as_deref().map(|domain_filter| {
+        let canonical_site = sites.canonical(domain_filter);
+        let site_domain = sites.registrable_domain(&canonical_site);
+        (canonical_site, site_domain)
+    });
+    let (results, total) = connection.run(move |c| {
+        let query = filter_passwords(authorized_user_id, &filters, site_lookup.as_ref());
+        let query = match filters.ordering.as_deref() {
+            Some("site") => query.order((passwords::site, passwords::id)),
+            Some("-site") => query.order((passwords::site.desc(), passwords::id)),
+            Some("created") => query.order((passwords::created, passwords::id)),
+            Some("-created") => query.order((passwords::created.desc(), passwords::id.desc())),
+            Some("modified") => query.order((passwords::modified, passwords::id)),
+            Some("-modified") => query.order((passwords::modified.desc(), passwords::id.desc())),
+            _ => query.order(passwords::id)
+        };
+        let results = query
+            .limit(page_limit.unwrap_or(-1))
+            .offset(page_offset)
+            .load::<Password>(c)?;
+        let total = match paginated {
+            true => filter_passwords(authorized_user_id, &filters, site_lookup.as_ref()).count().get_result::<i64>(c)?,
+            false => results.len() as i64
+        };
+        Ok::<(Vec<Password>, i64), diesel::result::Error>((results, total))
+    }).await.map_err(|e| RockpassError::internal(e, "There was a problem listing the password entries"))?;
+    if csv {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let written = results.into_iter().try_for_each(|result| writer.serialize(Password {
+            login: escape_formula(&result.login),
+            site: escape_formula(&result.site),
+            ..result
+        }));
+        return match written.map_err(|e| e.to_string()).and_then(|_| writer.into_inner().map_err(|e| e.to_string())) {
+            Ok(written) => Ok(Listing::Csv(Csv(String::from_utf8_lossy(&written).to_string()))),
+            Err(e) => Err(RockpassError::internal(e, "There was a problem writing the CSV"))
+        };
+    }
+    if ! paginated {
+        return Ok(Listing::Json(status::Custom(Status::Ok, Json(json!({"count": total, "results": results})))));
+    }
+    // Link the next and previous pages like the LessPass API does
+    let next_page = page_limit
+        .map(|page_limit| page_offset.saturating_add(page_limit))
+        .filter(|next_offset| *next_offset < total)
+        .map(|next_offset| page_uri(uri, next_offset));
+    let previous_page = (page_offset > 0)
+        .then(|| page_uri(uri, page_limit.map_or(0, |page_limit| (page_offset - page_limit).max(0))));
+    Ok(Listing::Json(status::Custom(Status::Ok, Json(
             json!({
-                "count": results.len(),
+                "count": total,
+                "next": next_page,
+                "previous": previous_page,
                 "results": results
             })
-        ))
+        ))))
 }
 
 #[post("/passwords", data = "<new_password>")]
-pub async fn post_passwords(authorization: Authorization, new_password: Json<NewPassword>) -> status::Custom<Json<Value>> {
+pub async fn post_passwords(authorization: Authorization, sites: &State<Sites>, new_password: JsonBody<NewPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
     let connection = authorization.0;
     // Insert new pasword in database
+    validate_profile(&new_password, "The password profile is not valid")?;
     let authorized_user_id = authorization.1.id;
     let new_password_to_insert = new_password.0.clone();
+    let new_password_canonical = sites.canonical(&new_password_to_insert.site);
     match connection.run(move |c| {
         diesel::insert_into(passwords)
-            .values((passwords::user_id.eq(&authorized_user_id), &new_password_to_insert))
+            .values((passwords::user_id.eq(&authorized_user_id), passwords::site_canonical.eq(new_password_canonical), &new_password_to_insert))
             .returning(Password::as_returning())
             .get_result(c)
     }).await {
-        Ok(inserted_row) => status::Custom(Status::Created, Json(json!(inserted_row))),
-        Err(_) => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem creating the new password entry"})))
+        Ok(inserted_row) => Ok(status::Custom(Status::Created, Json(json!(inserted_row)))),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(duplicated_password(&new_password.login, &new_password.site)),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem creating the new password entry"))
+    }
+}
+
+#[options("/passwords/export")]
+pub async fn options_passwords_export() -> Status {
+    Status::NoContent
+}
+
+#[get("/passwords/export")]
+pub async fn get_passwords_export(authorization: Authorization) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    // Return all the passwords of the user with the same shape used by LessPass
+    let authorized_user_id = authorization.1.id;
+    match connection.run(move |c| {
+        passwords::table
+            .filter(passwords::user_id.eq(&authorized_user_id))
+            .order(passwords::id)
+            .load::<Password>(c)
+    }).await {
+        Ok(results) => Ok(status::Custom(Status::Ok, Json(json!({"count": results.len(), "results": results})))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem exporting the password entries"))
+    }
+}
+
+#[options("/passwords/import")]
+pub async fn options_passwords_import() -> Status {
+    Status::NoContent
+}
+
+#[post("/passwords/import", data = "<passwords_import>", rank = 2)]
+pub async fn post_passwords_import(authorization: Authorization, sites: &State<Sites>, passwords_import: JsonBody<PasswordsImport>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let profiles = passwords_import.0.into_profiles();
+    for (position, profile) in profiles.iter().enumerate() {
+        validate_profile(profile, &format!("The profile {} is not valid", position + 1))?;
+    }
+    import_passwords(authorization, sites, profiles).await
+}
+
+#[post("/passwords/import", format = "text/csv", data = "<passwords_csv>", rank = 1)]
+pub async fn post_passwords_import_csv(authorization: Authorization, sites: &State<Sites>, limits: &Limits, passwords_csv: Data<'_>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // The CSV size is limited by the csv limit, 1MiB by default
+    let passwords_csv = match passwords_csv.open(limits.get("csv").unwrap_or(1.mebibytes())).into_string().await {
+        Ok(passwords_csv) if passwords_csv.is_complete() => passwords_csv.into_inner(),
+        Ok(_) => return Err(RockpassError::PayloadTooLarge("The CSV is too large".into())),
+        Err(_) => return Err(RockpassError::BadRequest("The CSV cannot be read".into()))
+    };
+    let mut profiles = Vec::new();
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(passwords_csv.as_bytes());
+    for (line, record) in reader.deserialize::<CsvPassword>().enumerate() {
+        // Count the header line too so the number matches the one shown by spreadsheets
+        match record.map_err(|e| e.to_string()).and_then(|record| NewPassword::try_from(record).map_err(|e| e.to_string())) {
+            Ok(profile) => {
+                validate_profile(&profile, &format!("The profile in line {} is not valid", line + 2))?;
+                profiles.push(profile)
+            },
+            Err(e) => return Err(RockpassError::UnprocessableEntity(format!("Invalid profile in line {}: {}", line + 2, e)))
+        }
+    }
+    import_passwords(authorization, sites, profiles).await
+}
+
+async fn import_passwords(authorization: Authorization, sites: &Sites, profiles: Vec<NewPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    // Import all the profiles in a single transaction, a profile with the same login and site
+    // than an existing one updates it, or it is skipped if nothing changes
+    let authorized_user_id = authorization.1.id;
+    let profiles = profiles.into_iter()
+        .map(|profile| (sites.canonical(&profile.site), profile))
+        .collect::<Vec<(String, NewPassword)>>();
+    match connection.run(move |c| {
+        c.transaction(|c| {
+            let mut report = Vec::new();
+            for (profile_canonical, profile) in profiles {
+                let existing_password = passwords::table
+                    .filter(passwords::user_id.eq(&authorized_user_id))
+                    .filter(passwords::login.eq(&profile.login))
+                    .filter(passwords::site.eq(&profile.site))
+                    .first::<Password>(c)
+                    .optional()?;
+                let (imported_password_id, status) = match existing_password {
+                    None => {
+                        let inserted_password_id = diesel::insert_into(passwords)
+                            .values((passwords::user_id.eq(&authorized_user_id), passwords::site_canonical.eq(&profile_canonical), &profile))
+                            .returning(passwords::id)
+                            .get_result::<i32>(c)?;
+                        (inserted_password_id, "created")
+                    },
+                    Some(existing_password) if existing_password.uppercase == profile.uppercase &&
+                        existing_password.symbols == profile.symbols &&
+                        existing_password.lowercase == profile.lowercase &&
+                        existing_password.digits == profile.digits &&
+                        existing_password.counter == profile.counter &&
+                        existing_password.version == profile.version &&
+                        existing_password.length == profile.length => (existing_password.id, "skipped"),
+                    Some(existing_password) => {
+                        diesel::update(passwords.find(existing_password.id))
+                            .set((&profile, passwords::site_canonical.eq(&profile_canonical), passwords::modified.eq(current_timestamp()), passwords::revision.eq(passwords::revision + 1)))
+                            .execute(c)?;
+                        (existing_password.id, "updated")
+                    }
+                };
+                report.push(json!({"id": imported_password_id, "login": profile.login, "site": profile.site, "status": status}));
+            }
+            Ok::<Vec<Value>, diesel::result::Error>(report)
+        })
+    }).await {
+        Ok(report) => {
+            let count = |status: &str| report.iter().filter(|entry| entry["status"] == status).count();
+            Ok(status::Custom(Status::Ok, Json(json!({
+                "created": count("created"),
+                "updated": count("updated"),
+                "skipped": count("skipped"),
+                "results": report
+            }))))
+        },
+        Err(e) => Err(RockpassError::internal(e, "There was a problem importing the password entries"))
+    }
+}
+
+fn current_timestamp() -> NaiveDateTime {
+    // Every write stores the dates in whole seconds, as the database defaults do, so they are
+    // compared and returned in the same way
+    Utc::now().naive_utc().trunc_subsecs(0)
+}
+
+fn parse_cursor(cursor: &str) -> Option<NaiveDateTime> {
+    // The cursor returned by the changes is the Unix time, but a date is also accepted
+    match cursor.parse::<i64>() {
+        Ok(seconds) => DateTime::from_timestamp(seconds, 0).map(|date| date.naive_utc()),
+        Err(_) => DateTime::parse_from_rfc3339(cursor).map(|date| date.naive_utc())
+            .or_else(|_| cursor.parse::<NaiveDateTime>())
+            .ok()
+    }
+}
+
+#[options("/passwords/changes")]
+pub async fn options_passwords_changes() -> Status {
+    Status::NoContent
+}
+
+#[get("/passwords/changes?<since>")]
+pub async fn get_passwords_changes(authorization: Authorization, since: Option<String>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    let connection = authorization.0;
+    let since_date = match since.as_deref().map(parse_cursor) {
+        Some(None) => return Err(RockpassError::BadRequest("The since cursor is not valid".into())),
+        since_date => since_date.flatten()
+    };
+    // The new cursor is taken before seeking and compared inclusively, so the changes made in
+    // the same second are sent again in the next sync instead of being lost
+    let cursor = Utc::now().timestamp();
+    let authorized_user_id = authorization.1.id;
+    match connection.run(move |c| {
+        let mut changed_query = passwords::table
+            .filter(passwords::user_id.eq(&authorized_user_id))
+            .order((passwords::modified, passwords::id))
+            .into_boxed();
+        let mut deleted_query = deleted_passwords::table
+            .filter(deleted_passwords::user_id.eq(&authorized_user_id))
+            .order(deleted_passwords::id)
+            .select(deleted_passwords::password_id)
+            .into_boxed();
+        if let Some(since_date) = since_date {
+            changed_query = changed_query.filter(passwords::modified.ge(since_date));
+            deleted_query = deleted_query.filter(deleted_passwords::deleted.ge(since_date));
+        }
+        let changed_passwords = changed_query.load::<Password>(c)?;
+        // Without cursor it is a full sync, so the deletions are not needed
+        let deleted_password_ids = match since_date {
+            Some(_) => deleted_query.load::<i32>(c)?,
+            None => Vec::new()
+        };
+        Ok::<(Vec<Password>, Vec<i32>), diesel::result::Error>((changed_passwords, deleted_password_ids))
+    }).await {
+        Ok((changed_passwords, deleted_password_ids)) => Ok(status::Custom(Status::Ok, Json(json!({
+            "cursor": cursor.to_string(),
+            "count": changed_passwords.len(),
+            "results": changed_passwords,
+            "deleted": deleted_password_ids
+        })))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem getting the changes"))
     }
 }
 
@@ -414,8 +1669,32 @@ pub async fn options_passwords_id(_password_id: i32) -> Status {
     Status::NoContent
 }
 
+fn etag(password_revision: i32) -> Header<'static> {
+    Header::new("ETag", format!("\"{password_revision}\""))
+}
+
+fn precondition_failed() -> RockpassError {
+    RockpassError::PreconditionFailed("The password entry has been modified since it was read".into())
+}
+
+fn validate_profile(profile: &NewPassword, detail: &str) -> Result<(), RockpassError> {
+    profile.validate().map_err(|fields| RockpassError::Invalid(detail.to_string(), fields))
+}
+
+fn duplicated_password(duplicated_login: &str, duplicated_site: &str) -> RockpassError {
+    RockpassError::Conflict(format!("There is already a password entry for login {duplicated_login} in site {duplicated_site}"))
+}
+
+fn password_exists(c: &mut SqliteConnection, password_id: i32, owner_id: i32) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        passwords::table
+            .filter(passwords::id.eq(password_id))
+            .filter(passwords::user_id.eq(owner_id))
+    )).get_result(c)
+}
+
 #[get("/passwords/<password_id>")]
-pub async fn get_passwords_id(authorization: Authorization, password_id: i32) -> status::Custom<Json<Value>> {
+pub async fn get_passwords_id(authorization: Authorization, password_id: i32) -> Result<Tagged, RockpassError> {
     let connection = authorization.0;
     // Seek for passwords in database
     let authorized_user_id = authorization.1.id;
@@ -427,47 +1706,203 @@ pub async fn get_passwords_id(authorization: Authorization, password_id: i32) ->
             .load::<Password>(c)
     }).await {
         Ok(results) => if results.is_empty() {
-            status::Custom(Status::NotFound, Json(json!({"detail": format!("Password {password_id} not found in database")})))
+            Err(RockpassError::NotFound(format!("Password {password_id} not found in database")))
         } else {
-            status::Custom(Status::Ok, Json(json!(results[0])))
+            Ok(Tagged(status::Custom(Status::Ok, Json(json!(results[0]))), etag(results[0].revision)))
         },
-        Err(_) => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem getting password entry"})))
+        Err(e) => Err(RockpassError::internal(e, "There was a problem getting password entry"))
     }
 }
 
 
 #[put("/passwords/<updated_password_id>", data = "<updated_password>")]
-pub async fn put_passwords_id(authorization: Authorization, updated_password_id: i32, updated_password: Json<NewPassword>) -> status::Custom<Json<Value>> {
+pub async fn put_passwords_id(authorization: Authorization, sites: &State<Sites>, if_match: IfMatch, updated_password_id: i32, updated_password: JsonBody<NewPassword>) -> Result<Tagged, RockpassError> {
     let connection = authorization.0;
-    // Update existing password
+    // Update existing password, only if it was not modified since the client read it when the
+    // request is conditional
+    validate_profile(&updated_password, "The password profile is not valid")?;
     let authorized_user_id = authorization.1.id;
     let updated_password_to_insert = updated_password.0.clone();
-    let updated_rows = connection.run(move |c| {
-        diesel::update(passwords)
-            .filter(passwords::id.eq(updated_password_id))
-            .filter(passwords::user_id.eq(&authorized_user_id))
-            .set((&updated_password_to_insert, passwords::modified.eq(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())))
-        .execute(c)
-    }).await.unwrap_or_default();
-    match updated_rows {
-        0 => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem updating the password entry"}))),
-        _ => status::Custom(Status::Created, Json(json!({"detail": format!("Updated password entry for site {}", updated_password.0.site)})))
+    let updated_password_canonical = sites.canonical(&updated_password_to_insert.site);
+    let if_match_revisions = if_match.revisions();
+    let updated = connection.run(move |c| {
+        c.transaction(|c| {
+            let mut query = diesel::update(passwords)
+                .filter(passwords::id.eq(updated_password_id))
+                .filter(passwords::user_id.eq(&authorized_user_id))
+                .set((&updated_password_to_insert, passwords::site_canonical.eq(&updated_password_canonical), passwords::modified.eq(current_timestamp()), passwords::revision.eq(passwords::revision + 1)))
+                .into_boxed();
+            if let Some(if_match_revisions) = if_match_revisions {
+                query = query.filter(passwords::revision.eq_any(if_match_revisions));
+            }
+            match query.execute(c)? {
+                0 => Ok(Err(password_exists(c, updated_password_id, authorized_user_id)?)),
+                _ => passwords.find(updated_password_id).select(passwords::revision).first::<i32>(c).map(Ok)
+            }
+        })
+    }).await;
+    match updated {
+        Ok(Ok(updated_revision)) => Ok(Tagged(status::Custom(Status::Created, Json(json!({"detail": format!("Updated password entry for site {}", updated_password.0.site)}))), etag(updated_revision))),
+        Ok(Err(true)) => Err(precondition_failed()),
+        Ok(Err(false)) => Err(RockpassError::NotFound(format!("Password {updated_password_id} not found in database"))),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(duplicated_password(&updated_password.login, &updated_password.site)),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem updating the password entry"))
+    }
+}
+
+#[patch("/passwords/<patched_password_id>", data = "<password_changes>")]
+pub async fn patch_passwords_id(authorization: Authorization, sites: &State<Sites>, if_match: IfMatch, patched_password_id: i32, password_changes: JsonBody<PasswordChanges>) -> Result<Tagged, RockpassError> {
+    let connection = authorization.0;
+    // Change only the fields sent, with the same precondition than a full update
+    if password_changes.is_empty() {
+        return Err(RockpassError::BadRequest("There are no fields to update".into()));
+    }
+    let authorized_user_id = authorization.1.id;
+    let changed_site_canonical = password_changes.site.as_deref().map(|changed_site| sites.canonical(changed_site));
+    let if_match_revisions = if_match.revisions();
+    let patched = connection.run(move |c| {
+        c.transaction(|c| {
+            // The profile that results of the changes must be valid too
+            let current_password = passwords::table
+                .filter(passwords::id.eq(patched_password_id))
+                .filter(passwords::user_id.eq(&authorized_user_id))
+                .first::<Password>(c)
+                .optional()?;
+            let changed_password = current_password.map(|current_password| NewPassword::from(current_password).changed(&password_changes));
+            if let Some(changed_password) = &changed_password &&
+                let Err(e) = validate_profile(changed_password, "The password profile is not valid") {
+                return Ok(Err(e));
+            }
+            let mut query = diesel::update(passwords)
+                .filter(passwords::id.eq(patched_password_id))
+                .filter(passwords::user_id.eq(&authorized_user_id))
+                .set((&password_changes.0, changed_site_canonical.map(|changed_site_canonical| passwords::site_canonical.eq(changed_site_canonical)), passwords::modified.eq(current_timestamp()), passwords::revision.eq(passwords::revision + 1)))
+                .into_boxed();
+            if let Some(if_match_revisions) = if_match_revisions {
+                query = query.filter(passwords::revision.eq_any(if_match_revisions));
+            }
+            match query.execute(c) {
+                Ok(0) if password_exists(c, patched_password_id, authorized_user_id)? => Ok(Err(precondition_failed())),
+                Ok(0) => Ok(Err(RockpassError::NotFound(format!("Password {patched_password_id} not found in database")))),
+                Ok(_) => passwords.find(patched_password_id).first::<Password>(c).map(Ok),
+                Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) if let Some(changed_password) = &changed_password =>
+                    Ok(Err(duplicated_password(&changed_password.login, &changed_password.site))),
+                Err(e) => Err(e)
+            }
+        })
+    }).await;
+    match patched {
+        Ok(Ok(patched_password)) => Ok(Tagged(status::Custom(Status::Ok, Json(json!(patched_password))), etag(patched_password.revision))),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem updating the password entry"))
     }
 }
 
 #[delete("/passwords/<deleted_password_id>")]
-pub async fn delete_passwords_id(authorization: Authorization, deleted_password_id: i32) -> status::Custom<Json<Value>> {
+pub async fn delete_passwords_id(authorization: Authorization, if_match: IfMatch, deleted_password_id: i32) -> Result<status::Custom<Json<Value>>, RockpassError> {
     let connection = authorization.0;
-    // Delete existing password
+    // Delete existing password leaving a tombstone so the clients that sync the changes know it,
+    // also only if it was not modified since the client read it when the request is conditional
     let authorized_user_id = authorization.1.id;
-    let deleted_rows = connection.run(move |c| {
-        diesel::delete(passwords)
-            .filter(passwords::id.eq(deleted_password_id))
+    let if_match_revisions = if_match.revisions();
+    let deleted = connection.run(move |c| {
+        c.transaction(|c| {
+            let mut query = diesel::delete(passwords)
+                .filter(passwords::id.eq(deleted_password_id))
+                .filter(passwords::user_id.eq(&authorized_user_id))
+                .into_boxed();
+            if let Some(if_match_revisions) = if_match_revisions {
+                query = query.filter(passwords::revision.eq_any(if_match_revisions));
+            }
+            match query.execute(c)? {
+                0 => Ok(Err(password_exists(c, deleted_password_id, authorized_user_id)?)),
+                _ => diesel::insert_into(deleted_passwords::table)
+                    .values((deleted_passwords::user_id.eq(&authorized_user_id), deleted_passwords::password_id.eq(deleted_password_id)))
+                    .execute(c)
+                    .map(Ok)
+            }
+        })
+    }).await;
+    match deleted {
+        Ok(Ok(_)) => Ok(status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted password with id {}", deleted_password_id)})))),
+        Ok(Err(true)) => Err(precondition_failed()),
+        Ok(Err(false)) => Err(RockpassError::NotFound(format!("Password {deleted_password_id} not found in database"))),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem deleting the password entry"))
+    }
+}
+
+#[options("/passwords/<_password_id>/render")]
+pub async fn options_passwords_id_render(_password_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/passwords/<rendered_password_id>/render", data = "<master_password>")]
+pub async fn post_passwords_id_render(authorization: Authorization, config: &State<RockpassConfig>, rendered_password_id: i32, master_password: JsonBody<MasterPassword>) -> Result<status::Custom<Json<Value>>, RockpassError> {
+    // The master password is sent to the server, so it must be enabled explicitly
+    if ! config.password_rendering_enabled {
+        return Err(RockpassError::Forbidden("Password rendering is disabled".into()));
+    }
+    let connection = authorization.0;
+    let authorized_user_id = authorization.1.id;
+    let rendered_password = connection.run(move |c| {
+        passwords::table
+            .filter(passwords::id.eq(rendered_password_id))
             .filter(passwords::user_id.eq(&authorized_user_id))
-            .execute(c)
-    }).await.unwrap_or_default();
-    match deleted_rows {
-        0 => status::Custom(Status::InternalServerError, Json(json!({"detail": "There was a problem deleting the password entry"}))),
-        _ => status::Custom(Status::Ok, Json(json!({"detail": format!("Deleted password with id {}", deleted_password_id)})))
+            .first::<Password>(c)
+            .optional()
+    }).await
+        .map_err(|e| RockpassError::internal(e, "There was a problem getting password entry"))?
+        .ok_or_else(|| RockpassError::NotFound(format!("Password {rendered_password_id} not found in database")))?;
+    // The key derivation is slow on purpose, so it runs out of the async workers
+    match rocket::tokio::task::spawn_blocking(move || lesspass::generate(&NewPassword::from(rendered_password), &master_password.0.master_password)).await {
+        Ok(Ok(generated_password)) => Ok(status::Custom(Status::Ok, Json(json!({"password": generated_password})))),
+        Ok(Err(e)) => Err(RockpassError::UnprocessableEntity(e)),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem rendering the password"))
+    }
+}
+
+#[options("/passwords/<_password_id>/migrate")]
+pub async fn options_passwords_id_migrate(_password_id: i32) -> Status {
+    Status::NoContent
+}
+
+#[post("/passwords/<migrated_password_id>/migrate")]
+pub async fn post_passwords_id_migrate(authorization: Authorization, if_match: IfMatch, migrated_password_id: i32) -> Result<Tagged, RockpassError> {
+    let connection = authorization.0;
+    // Convert a version 1 profile to version 2 keeping its counter and characters, only the
+    // length is cut to the maximum of version 2. The generated password changes, so the user
+    // must change it in the site
+    let authorized_user_id = authorization.1.id;
+    let if_match_revisions = if_match.revisions();
+    let migrated = connection.run(move |c| {
+        c.transaction(|c| {
+            let Some(current_password) = passwords::table
+                .filter(passwords::id.eq(migrated_password_id))
+                .filter(passwords::user_id.eq(&authorized_user_id))
+                .first::<Password>(c)
+                .optional()? else {
+                return Ok(Err(RockpassError::NotFound(format!("Password {migrated_password_id} not found in database"))));
+            };
+            if if_match_revisions.is_some_and(|if_match_revisions| ! if_match_revisions.contains(&current_password.revision)) {
+                return Ok(Err(precondition_failed()));
+            }
+            if current_password.version != 1 {
+                return Ok(Err(RockpassError::Conflict(format!("Password {migrated_password_id} is already version {}", current_password.version))));
+            }
+            diesel::update(passwords.find(migrated_password_id))
+                .set((
+                    passwords::version.eq(2),
+                    passwords::length.eq(current_password.length.min(MAX_PASSWORD_LENGTH)),
+                    passwords::modified.eq(current_timestamp()),
+                    passwords::revision.eq(passwords::revision + 1)
+                ))
+                .execute(c)?;
+            passwords.find(migrated_password_id).first::<Password>(c).map(Ok)
+        })
+    }).await;
+    match migrated {
+        Ok(Ok(migrated_password)) => Ok(Tagged(status::Custom(Status::Ok, Json(json!(migrated_password))), etag(migrated_password.revision))),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(RockpassError::internal(e, "There was a problem migrating the password entry"))
     }
 }
@@ -1,3 +1,42 @@
+diesel::table! {
+    deleted_passwords (id) {
+        id -> Integer,
+        user_id -> Integer,
+        password_id -> Integer,
+        deleted -> Timestamp,
+    }
+}
+
+diesel::table! {
+    invitations (id) {
+        id -> Integer,
+        code_hash -> Text,
+        max_uses -> Integer,
+        uses -> Integer,
+        expires -> Timestamp,
+        created_by -> Nullable<Integer>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    jwt_secrets (kid) {
+        kid -> Text,
+        secret -> Text,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_failures (scope, subject) {
+        scope -> Text,
+        subject -> Text,
+        failures -> Integer,
+        locked_until -> Nullable<Timestamp>,
+        modified -> Timestamp,
+    }
+}
+
 diesel::table! {
     passwords (id) {
         id -> Integer,
@@ -13,6 +52,27 @@ diesel::table! {
         length -> Integer,
         created -> Timestamp,
         modified -> Timestamp,
+        revision -> Integer,
+        site_canonical -> Text,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code -> Text,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    retired_tokens (id) {
+        id -> Integer,
+        token_id -> Integer,
+        user_id -> Integer,
+        refresh_token -> Text,
+        created -> Timestamp,
     }
 }
 
@@ -24,6 +84,19 @@ diesel::table! {
         refresh_token -> Text,
         created -> Timestamp,
         modified -> Timestamp,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    totp_secrets (user_id) {
+        user_id -> Integer,
+        secret -> Text,
+        confirmed -> Bool,
+        last_used_step -> Nullable<BigInt>,
+        created -> Timestamp,
+        modified -> Timestamp,
     }
 }
 
@@ -32,14 +105,30 @@ diesel::table! {
         id -> Integer,
         email -> Text,
         password -> Text,
+        generation -> Integer,
+        is_admin -> Bool,
+        disabled -> Bool,
     }
 }
 
+diesel::joinable!(deleted_passwords -> users (user_id));
+diesel::joinable!(invitations -> users (created_by));
 diesel::joinable!(passwords -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(retired_tokens -> tokens (token_id));
+diesel::joinable!(retired_tokens -> users (user_id));
 diesel::joinable!(tokens -> users (user_id));
+diesel::joinable!(totp_secrets -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    deleted_passwords,
+    invitations,
+    jwt_secrets,
+    login_failures,
     passwords,
+    recovery_codes,
+    retired_tokens,
     tokens,
+    totp_secrets,
     users,
 );
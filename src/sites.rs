@@ -0,0 +1,85 @@
+//
+// sites.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use std::fs;
+use std::net::IpAddr;
+
+use diesel::{self, prelude::*};
+use publicsuffix::{List, Psl};
+use url::Url;
+
+use crate::RockpassConfig;
+use crate::schema::passwords;
+
+pub struct Sites {
+    public_suffixes: Option<List>,
+    strip_www: bool
+}
+
+impl Sites {
+    pub fn from_config(config: &RockpassConfig) -> Result<Sites, String> {
+        // Without list the registrable domains are unknown, taking only the last label as public
+        // suffix would mix example.co.uk with other.co.uk
+        let public_suffix_list = &config.public_suffix_list;
+        if public_suffix_list.is_empty() {
+            return Ok(Sites { public_suffixes: None, strip_www: config.strip_www });
+        }
+        let list = fs::read(public_suffix_list).map_err(|e| format!("Cannot read the public suffix list {}: {}", public_suffix_list, e))?;
+        let public_suffixes = List::from_bytes(&list).map_err(|e| format!("Cannot parse the public suffix list {}: {}", public_suffix_list, e))?;
+        Ok(Sites { public_suffixes: Some(public_suffixes), strip_www: config.strip_www })
+    }
+
+    pub fn canonical(&self, site: &str) -> String {
+        canonical(site, self.strip_www)
+    }
+
+    pub fn registrable_domain(&self, canonical_site: &str) -> Option<String> {
+        // Domain that can be registered, for example example.co.uk for login.example.co.uk, the
+        // addresses, the public suffixes and the sites that are not domains do not have it, and
+        // neither any site without list
+        if canonical_site.starts_with('[') || canonical_site.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        self.public_suffixes.as_ref()?.domain(canonical_site.as_bytes())
+            .and_then(|domain| std::str::from_utf8(domain.as_bytes()).ok().map(|domain| domain.to_string()))
+    }
+}
+
+pub fn canonical(site: &str, strip_www: bool) -> String {
+    // Only the host of the site, in lowercase, with the international names in punycode and
+    // optionally without the www prefix. The site is stored as the user wrote it because
+    // LessPass uses it to generate the password
+    let site = site.trim();
+    let url = if site.contains("://") { Url::parse(site) } else { Url::parse(&format!("http://{site}")) };
+    let host = match url.as_ref().ok().and_then(|url| url.host_str()) {
+        Some(host) if ! host.is_empty() => host.trim_end_matches('.').to_string(),
+        _ => site.to_lowercase()
+    };
+    match host.strip_prefix("www.") {
+        Some(stripped_host) if strip_www && stripped_host.contains('.') => stripped_host.to_string(),
+        _ => host
+    }
+}
+
+pub fn update_stored(c: &mut SqliteConnection, strip_www: bool) -> QueryResult<usize> {
+    // Fill the canonical site of the passwords stored before it existed or with the other
+    // setting of the www prefix
+    let pending_sites = passwords::table
+        .select((passwords::id, passwords::site, passwords::site_canonical))
+        .load::<(i32, String, String)>(c)?
+        .into_iter()
+        .map(|(password_id, password_site, site_canonical)| (password_id, canonical(&password_site, strip_www), site_canonical))
+        .filter(|(_, password_canonical, site_canonical)| password_canonical != site_canonical)
+        .collect::<Vec<(i32, String, String)>>();
+    c.transaction(|c| {
+        for (password_id, password_canonical, _) in &pending_sites {
+            diesel::update(passwords::table.find(password_id))
+                .set(passwords::site_canonical.eq(password_canonical))
+                .execute(c)?;
+        }
+        Ok(pending_sites.len())
+    })
+}
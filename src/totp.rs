@@ -0,0 +1,82 @@
+//
+// totp.rs
+// Copyright (C) 2026 Óscar García Amor <ogarcia@connectical.com>
+// Distributed under terms of the GNU GPLv3 license.
+//
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore, rngs::OsRng};
+use rocket::http::RawStr;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+// RFC 6238 parameters, the defaults understood by every authenticator application
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+// Number of time steps accepted before and after the current one to absorb clock drift
+const TOTP_SKEW: i64 = 1;
+// Length in bytes of the shared secret (160 bits as recommended by RFC 4226)
+const SECRET_LENGTH: usize = 20;
+// Recovery codes are generated as groups of characters separated by a dash
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_GROUPS: usize = 2;
+const RECOVERY_CODE_GROUP_LENGTH: usize = 5;
+const RECOVERY_CODE_CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+pub fn new_secret() -> String {
+    // Generate a random secret and return it base32 encoded
+    let mut secret = [0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+pub fn provisioning_uri(secret: &str, user_email: &str) -> String {
+    // Build the URI used by authenticator applications (usually shown as QR code)
+    format!("otpauth://totp/Rockpass:{}?secret={}&issuer=Rockpass&algorithm=SHA1&digits={}&period={}",
+        RawStr::new(user_email).percent_encode(), secret, TOTP_DIGITS, TOTP_PERIOD)
+}
+
+#[cfg(test)]
+pub fn code(secret: &str, timestamp: i64) -> Option<String> {
+    // Return the one-time code valid at given UNIX timestamp
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    Some(code_at_step(&secret, timestamp.div_euclid(TOTP_PERIOD)))
+}
+
+pub fn verify(secret: &str, code: &str, timestamp: i64, last_used_step: Option<i64>) -> Option<i64> {
+    // Check the code against the steps inside the skew window and return the matched step, steps
+    // equal or older than the last used one are rejected so a code cannot be replayed
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current_step = timestamp.div_euclid(TOTP_PERIOD);
+    (current_step - TOTP_SKEW..=current_step + TOTP_SKEW)
+        .filter(|step| last_used_step.is_none_or(|last_used_step| *step > last_used_step))
+        .find(|step| code_at_step(&secret, *step).as_bytes().ct_eq(code.trim().as_bytes()).into())
+}
+
+pub fn is_code(code: &str) -> bool {
+    // One-time codes are only digits, anything else is treated as a recovery code
+    let code = code.trim();
+    code.len() == TOTP_DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+pub fn new_recovery_codes() -> Vec<String> {
+    let mut rng = OsRng;
+    (0..RECOVERY_CODES).map(|_| {
+        (0..RECOVERY_CODE_GROUPS).map(|_| {
+            (0..RECOVERY_CODE_GROUP_LENGTH)
+                .map(|_| RECOVERY_CODE_CHARSET[rng.gen_range(0..RECOVERY_CODE_CHARSET.len())] as char)
+                .collect::<String>()
+        }).collect::<Vec<String>>().join("-")
+    }).collect()
+}
+
+fn code_at_step(secret: &[u8], step: i64) -> String {
+    // HOTP value (RFC 4226) for the given counter
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize)
+}